@@ -1,14 +1,45 @@
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use image::{ImageBuffer, ImageReader, Rgba};
-use rand::{RngCore, rng};
-use rodio::{Decoder, OutputStream, OutputStreamBuilder, Sink};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::Local;
+use glob::Pattern;
+use image::{
+    AnimationDecoder, ImageReader, Rgba, RgbaImage,
+    codecs::gif::GifDecoder,
+    imageops::{self, FilterType},
+};
+use log::{debug, error, info, warn};
+use rand::{
+    Rng, RngCore, SeedableRng,
+    distr::{Distribution, weighted::WeightedIndex},
+    rngs::StdRng,
+    seq::IndexedRandom,
+};
+use rodio::{Decoder, Source, buffer::SamplesBuffer, source::ChannelVolume};
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     output::{OutputHandler, OutputState},
+    reexports::calloop::{
+        LoopHandle, RegistrationToken,
+        channel::{Event, Sender, channel},
+        timer::{TimeoutAction, Timer},
+    },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
+    seat::{
+        Capability, SeatHandler, SeatState,
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RawModifiers},
+        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+    },
     shell::{
         WaylandSurface,
         wlr_layer::{
@@ -18,301 +49,4768 @@ use smithay_client_toolkit::{
     },
     shm::{Shm, ShmHandler, slot::SlotPool},
 };
-use wayland_client::{Connection, QueueHandle, protocol::wl_output::WlOutput};
+use wayland_client::{
+    Connection, QueueHandle,
+    protocol::{
+        wl_keyboard::WlKeyboard,
+        wl_output::{Transform, WlOutput},
+        wl_pointer::WlPointer,
+        wl_seat::WlSeat,
+        wl_shm::Format,
+        wl_surface::WlSurface,
+    },
+};
+
+use crate::{
+    cache::{Image, ImageCache, Picture},
+    caption::{Captioner, caption_for, caption_position},
+    config::{
+        Animation, AudioOverlapPolicy, Backdrop, Config, DismissSeat, DurationMode, Easing,
+        HideAudioBehavior, ImageTransform, OverlayLayer, Placement, Position, ScaleFilter,
+        ScaleMode, Transition,
+    },
+    hook::Hooks,
+    pack,
+    playlist::Playlist,
+    schedule::{self, ActiveWindow, DatedShow},
+    sound::{self, SoundPool},
+    status::{self, Status},
+};
+
+/// How many images to try decoding before giving up on a show.
+const MAX_IMAGE_ATTEMPTS: usize = 3;
+
+/// Bytes the shm pool starts out with.
+const INITIAL_POOL_SIZE: usize = 64 * 64 * 4;
+/// Largest buffer width or height drawn, anything bigger is a bogus configure or would take
+/// gigabytes of shm.
+const MAX_BUFFER_DIMENSION: u32 = 16384;
+
+/// How often the sink is checked for the end of the clip when the overlay follows the audio.
+const AUDIO_POLL: Duration = Duration::from_millis(100);
+/// Shortest time between overlays, however the jitter rolls.
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the sink volume is updated while fading.
+const FADE_STEP: Duration = Duration::from_millis(10);
 
 pub struct App {
+    loop_handle: LoopHandle<'static, App>,
+    qh: QueueHandle<App>,
     output_state: OutputState,
     layer_shell: LayerShell,
     shm: Shm,
     compositor_state: CompositorState,
     registry_state: RegistryState,
+    seat_state: SeatState,
+    /// One per seat, only bound with `dismiss_on_key`.
+    keyboards: Vec<(WlSeat, WlKeyboard)>,
+    dismiss_on_key: bool,
+    /// One per seat, only bound with `dismiss_on_click`.
+    pointers: Vec<(WlSeat, WlPointer)>,
+    dismiss_on_click: bool,
+    dismiss_seat: DismissSeat,
+    /// Seats that dismissed the current show, for `dismiss_seat = "all"`.
+    dismissed_by: Vec<WlSeat>,
     pool: SlotPool,
-    layer_surfaces: HashMap<WlOutput, LayerSurface>,
+    /// Every random choice comes from here, so a seed makes the whole session reproducible.
+    rng: StdRng,
+    layer_surfaces: HashMap<WlOutput, OutputSurface>,
+    outputs: Vec<String>,
+    output_pan: HashMap<String, f32>,
+    /// Name or description of the only output the next show goes on, from the `output` command.
+    target_output: Option<String>,
+    /// The output the current show is limited to, `None` while it's on all of them.
+    shown_only_on: Option<WlOutput>,
+    position: Position,
+    margin: i32,
+    layer: OverlayLayer,
+    exclusive_zone: i32,
+    size: (u32, u32),
     shown: bool,
-    width: u32,
-    height: u32,
-    image_path: Option<PathBuf>,
+    /// A show waiting for the first surface to be configured, so it isn't skipped for lack of
+    /// outputs.
+    show_pending: bool,
+    next_toggle: Instant,
+    show_interval: Duration,
+    jitter: Duration,
+    /// Shortest time from any show, manual ones included, to the next automatic one.
+    cooldown: Duration,
+    last_show: Option<Instant>,
+    max_shows_per_minute: Option<usize>,
+    /// When the shows of the last minute started, oldest first.
+    recent_shows: VecDeque<Instant>,
+    visible_duration: Duration,
+    /// Hide when the audio ends instead of after `visible_duration`, `max_duration` at the latest.
+    follow_audio: bool,
+    max_duration: Duration,
+    scale_mode: ScaleMode,
+    scale_filter: FilterType,
+    size_limits: SizeLimits,
+    image_opacity: f32,
+    image_tint: Option<Rgba<u8>>,
+    /// What each show's transform is picked from.
+    transforms: Vec<ImageTransform>,
+    transform: ImageTransform,
+    background: Rgba<u8>,
+    backdrop: Backdrop,
+    letterbox: Option<Rgba<u8>>,
+    captioner: Option<Captioner>,
+    animation: Animation,
+    bounce_speed: f32,
+    transition: Transition,
+    transition_duration: Duration,
+    images: MediaDir,
+    music: MediaDir,
+    /// Where the packs are, each show then picks its images and audio from one of them.
+    packs_dir: Option<PathBuf>,
+    /// Always use the pack with this name.
+    pack: Option<String>,
+    ignore: Vec<Pattern>,
+    image_picker: Picker,
+    audio_picker: Picker,
+    image_cache: ImageCache,
+    max_image_pixels: u64,
+    /// Let every output pick its own image instead of showing the same one everywhere.
+    per_output_images: bool,
+    images_per_show: usize,
+    placement: Placement,
+    /// How many groups of images a show flashes through, 1 or less is no burst.
+    burst_count: usize,
+    burst_frame: Duration,
     audio_path: Option<PathBuf>,
-    output_stream: OutputStream,
-    sink: Sink,
+    last_audio: Option<PathBuf>,
+    /// Images to the clips that always play with them, in order.
+    pairs: HashMap<PathBuf, Vec<PathBuf>>,
+    dated_shows: Vec<DatedShow>,
+    /// The clip today's dated show plays instead of a pick, if it has one.
+    dated_audio: Option<PathBuf>,
+    volume: f32,
+    normalize: bool,
+    /// Repeat the clip until the overlay hides.
+    loop_audio: bool,
+    /// Normalization gains of the clips analyzed so far.
+    normalized_gains: HashMap<PathBuf, f32>,
+    fade_in: Duration,
+    fade_out: Duration,
+    audio_overlap: AudioOverlapPolicy,
+    hide_audio: HideAudioBehavior,
+    /// The clip paused by the last hide, resumed by the next show.
+    paused_audio: Option<PathBuf>,
+    fade: Option<Fade>,
+    fade_timer: Option<RegistrationToken>,
+    fade_duration: Duration,
+    fade_easing: Easing,
+    visual_fade: Option<VisualFade>,
+    /// Incremented on every show so audio decoded for an earlier show can be discarded.
+    show_id: u64,
+    /// Whether to print status lines, and the last show they were printed for.
+    status_json: bool,
+    reported_show: u64,
+    /// Commands run when an overlay shows and hides.
+    hooks: Hooks,
+    /// Where `snapshot` saves each output's overlay, with `{output}` in it for the name.
+    snapshot_path: PathBuf,
+    /// Whether a clip was started for the current show.
+    audio_playing: bool,
+    decoded_audio: Sender<DecodedAudio>,
+    no_audio: bool,
+    muted: bool,
+    /// Local times of day no audio plays in, on top of `muted`.
+    mute_hours: Vec<ActiveWindow>,
+    silent_chance: f64,
+    /// Whether the current show rolled to play no audio.
+    silent: bool,
+    /// `None` with `no_audio` or while no audio device could be opened.
+    sounds: Option<SoundPool>,
+    max_sounds: usize,
 }
 
 impl App {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         output_state: OutputState,
         layer_shell: LayerShell,
         shm: Shm,
         compositor_state: CompositorState,
         registry_state: RegistryState,
+        seat_state: SeatState,
+        loop_handle: LoopHandle<'static, App>,
+        qh: QueueHandle<App>,
+        config: &Config,
     ) -> Result<Self> {
-        let pool = SlotPool::new(1920 * 1080 * 4, &shm)?; // we'll resize this later
-        let output_stream =
-            OutputStreamBuilder::open_default_stream().expect("open default audio stream");
-        let sink = rodio::Sink::connect_new(&output_stream.mixer());
+        // just a placeholder, render grows it to fit the largest surface it draws
+        let pool = SlotPool::new(INITIAL_POOL_SIZE, &shm)?;
 
-        Ok(Self {
+        let (decoded_audio, decoded_channel) = channel();
+        loop_handle
+            .insert_source(decoded_channel, |event, _, app| {
+                if let Event::Msg(decoded) = event {
+                    app.play_decoded(decoded);
+                }
+            })
+            .map_err(|e| anyhow!("inserting audio decoder channel: {e}"))?;
+
+        let mut app = Self {
+            loop_handle,
+            qh,
             output_state,
             layer_shell,
             shm,
             compositor_state,
             registry_state,
+            seat_state,
+            keyboards: Vec::new(),
+            dismiss_on_key: config.dismiss_on_key,
+            pointers: Vec::new(),
+            dismiss_on_click: config.dismiss_on_click,
+            dismiss_seat: config.dismiss_seat.clone(),
+            dismissed_by: Vec::new(),
             pool,
+            rng: match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_os_rng(),
+            },
             layer_surfaces: HashMap::new(),
+            outputs: config.outputs.clone(),
+            output_pan: config.output_pan.clone(),
+            target_output: None,
+            shown_only_on: None,
+            position: config.position,
+            margin: config.margin,
+            layer: config.layer,
+            exclusive_zone: config.exclusive_zone,
+            size: (config.width, config.height),
             shown: false,
-            width: 0,
-            height: 0,
-            image_path: None,
+            show_pending: false,
+            next_toggle: Instant::now(),
+            show_interval: Duration::from_secs(config.interval_secs),
+            jitter: Duration::from_secs_f64(config.jitter_secs.max(0.0)),
+            cooldown: Duration::from_secs(config.cooldown_secs),
+            last_show: None,
+            max_shows_per_minute: config.max_shows_per_minute,
+            recent_shows: VecDeque::new(),
+            visible_duration: Duration::from_secs(config.duration_secs),
+            follow_audio: config.duration == DurationMode::Audio,
+            max_duration: Duration::from_secs(config.max_duration_secs),
+            scale_mode: config.scale,
+            scale_filter: filter_for(config.scale_filter),
+            image_opacity: clamp_unit(config.image_opacity, "image_opacity"),
+            image_tint: config.image_tint,
+            transforms: config.transforms.clone(),
+            transform: ImageTransform::None,
+            size_limits: SizeLimits {
+                min: config.min_image_size.map(f64::from),
+                max: config.max_image_size.map(f64::from),
+            },
+            background: config.background,
+            backdrop: config.backdrop,
+            letterbox: config.letterbox_color,
+            captioner: match (&config.caption_font, config.show_caption) {
+                (Some(font), true) => Some(Captioner::load(
+                    font,
+                    config.caption_size,
+                    config.caption_color,
+                )?),
+                _ => None,
+            },
+            animation: config.animation,
+            bounce_speed: config.bounce_speed,
+            transition: config.transition,
+            transition_duration: Duration::from_millis(config.transition_ms),
+            images: match &config.image_playlist {
+                Some(playlist) => MediaDir::images(playlist.clone()).read_as_playlist(),
+                None => MediaDir::images(config.image_dir.clone()),
+            },
+            music: match &config.music_playlist {
+                Some(playlist) => MediaDir::audio(playlist.clone()).read_as_playlist(),
+                None => MediaDir::audio(config.music_dir.clone()),
+            },
+            packs_dir: config.packs_dir.clone(),
+            pack: config.pack.clone(),
+            ignore: config.ignore_patterns()?,
+            image_picker: if config.shuffle_images {
+                Picker::Shuffle(Playlist::default())
+            } else {
+                match config.load_weights()? {
+                    weights if weights.is_empty() => Picker::Random,
+                    weights => Picker::Weighted(weights),
+                }
+            },
+            audio_picker: Picker::Shuffle(Playlist::default()),
+            image_cache: ImageCache::new(config.image_cache_size),
+            max_image_pixels: config.max_image_pixels,
+            per_output_images: config.per_output_images,
+            images_per_show: config.images_per_show,
+            placement: config.placement,
+            burst_count: config.burst_count,
+            burst_frame: Duration::from_millis(config.burst_frame_ms),
             audio_path: None,
-            output_stream,
-            sink,
-        })
+            last_audio: None,
+            pairs: config.load_pairs()?,
+            dated_shows: config.schedule.clone(),
+            dated_audio: None,
+            volume: clamp_unit(config.volume, "volume"),
+            normalize: config.normalize,
+            loop_audio: config.loop_audio,
+            normalized_gains: HashMap::new(),
+            fade_in: Duration::from_millis(config.fade_in_ms),
+            fade_out: Duration::from_millis(config.fade_out_ms),
+            audio_overlap: config.audio_overlap,
+            hide_audio: config.hide_audio_behavior,
+            paused_audio: None,
+            fade: None,
+            fade_timer: None,
+            fade_duration: Duration::from_millis(config.fade_ms),
+            fade_easing: config.fade_easing,
+            visual_fade: None,
+            show_id: 0,
+            status_json: false,
+            reported_show: 0,
+            hooks: Hooks {
+                on_show: config.on_show_cmd.clone(),
+                on_hide: config.on_hide_cmd.clone(),
+            },
+            snapshot_path: config.snapshot_path.clone(),
+            audio_playing: false,
+            decoded_audio,
+            no_audio: config.no_audio,
+            muted: false,
+            mute_hours: config.mute_hours.clone(),
+            silent_chance: config.silent_chance,
+            silent: false,
+            sounds: None,
+            max_sounds: config.max_concurrent_sounds,
+        };
+        if let Err(e) = app.open_audio() {
+            warn!("showing overlays silently until an audio device shows up: {e:#}");
+        }
+
+        Ok(app)
+    }
+
+    /// Opens the default audio device unless it's already open or audio is disabled. Failing
+    /// isn't fatal, overlays are shown silently and every show tries again.
+    fn open_audio(&mut self) -> Result<()> {
+        if self.no_audio || self.sounds.is_some() {
+            return Ok(());
+        }
+
+        self.sounds = Some(SoundPool::open(self.max_sounds)?);
+
+        Ok(())
     }
 
     pub fn toggle_overlay(&mut self) {
-        for layer in self.layer_surfaces.values() {
-            let surface = layer.wl_surface();
+        if self.shown {
+            self.hide();
+        } else {
+            self.show();
+        }
+    }
 
-            if self.shown {
-                self.sink.stop();
-                surface.attach(None, 0, 0);
-                surface.commit();
-            } else {
-                self.image_path = None;
-                self.audio_path = None;
-                layer.set_size(0, 0);
-                layer.commit();
-            }
+    /// Replaces the overlay with a new pick, crossfading into it if that's configured, or shows
+    /// one if it's hidden.
+    pub fn next_overlay(&mut self) {
+        if self.shown && self.transition == Transition::None {
+            self.hide();
         }
 
-        self.shown = !self.shown;
+        self.present();
     }
-}
 
-impl ShmHandler for App {
-    fn shm_state(&mut self) -> &mut Shm {
-        &mut self.shm
+    /// Shows an overlay, unless one is already up.
+    pub fn show(&mut self) {
+        if !self.shown {
+            self.present();
+        }
     }
-}
 
-impl OutputHandler for App {
-    fn output_state(&mut self) -> &mut OutputState {
-        &mut self.output_state
+    /// Shows an overlay as soon as there's a configured surface to draw it on, right away if
+    /// there already is one.
+    pub fn show_when_ready(&mut self) {
+        if self.layer_surfaces.values().any(|target| target.configured) {
+            self.show();
+        } else {
+            debug!("showing once an output is ready");
+            self.show_pending = true;
+        }
     }
 
-    fn new_output(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, output: WlOutput) {
-        let surface = self.compositor_state.create_surface(qh);
-        let layer_surface = self.layer_shell.create_layer_surface(
-            qh,
-            surface,
-            Layer::Top,
-            Some("rbar"),
-            Some(&output),
-        );
+    /// Hides the overlay, if it's up.
+    pub fn hide(&mut self) {
+        if !self.shown {
+            return;
+        }
 
-        layer_surface.set_anchor(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM);
-        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::None);
-        layer_surface.set_size(0, 0);
-        layer_surface.set_exclusive_zone(-1);
-        layer_surface.commit();
-        self.layer_surfaces.insert(output, layer_surface);
-    }
+        debug!("hiding overlay");
+        self.shown = false;
+        self.report(&Status::Hide);
+        let end = match self.hide_audio {
+            HideAudioBehavior::Stop => FadeEnd::Stop,
+            HideAudioBehavior::Pause => {
+                self.paused_audio = self.audio_path.clone().filter(|_| self.audio_playing);
+                FadeEnd::Pause
+            }
+        };
+        self.fade_volume(0.0, self.fade_out, end);
 
-    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+        if self.fade_duration.is_zero() {
+            self.detach_surfaces();
+        } else {
+            self.visual_fade = Some(VisualFade {
+                start: Instant::now(),
+                showing: false,
+            });
+
+            for output in self.mapped_outputs() {
+                self.render(&output);
+            }
+        }
 
-    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+        self.next_toggle = Instant::now() + self.next_timeout();
     }
-}
 
-impl LayerShellHandler for App {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {}
+    /// Picks new images and shows them, transitioning from the current overlay if it's up.
+    /// Without any outputs there's nothing to show on, so the show is skipped.
+    fn present(&mut self) {
+        if self.layer_surfaces.is_empty() {
+            info!("no outputs to show the overlay on");
+            self.skip_show();
+            return;
+        }
 
-    fn configure(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        layer: &LayerSurface,
-        configure: LayerSurfaceConfigure,
-        _serial: u32,
-    ) {
-        if self.image_path.is_none() {
-            self.image_path = Some(random_image());
+        debug!("showing overlay");
+        self.shown_only_on = self.target_output.take().and_then(|name| {
+            let output = self.output_named(&name);
+            if output.is_none() {
+                warn!("no output named {name:?} to show on, showing on all of them");
+            }
+            output
+        });
+        if let Some(only) = &self.shown_only_on {
+            for (output, target) in &mut self.layer_surfaces {
+                if output != only && target.mapped {
+                    target.detach();
+                }
+            }
+        }
+        let crossfading = self.start_transitions();
+
+        self.shown = true;
+        self.dismissed_by.clear();
+        self.last_show = Some(Instant::now());
+        if self.max_shows_per_minute.is_some() {
+            self.recent_shows.push_back(Instant::now());
         }
+        self.show_id += 1;
+        self.audio_playing = false;
 
-        if self.audio_path.is_none() {
-            self.audio_path = Some(random_audio());
+        if let Err(e) = self.open_audio() {
+            debug!("still no audio: {e:#}");
+        }
+        for target in self.layer_surfaces.values_mut() {
+            target.show.last_image = std::mem::take(&mut target.show.image_paths).pop();
         }
+        self.last_audio = self.audio_path.take();
+        self.silent = self.rng.random_bool(self.silent_chance);
+        // a crossfade already blends the new image in
+        self.visual_fade = (!self.fade_duration.is_zero() && !crossfading).then(|| VisualFade {
+            start: Instant::now(),
+            showing: true,
+        });
 
-        let (width, height) = configure.new_size;
-        self.width = width;
-        self.height = height;
+        self.enter_pack();
+        self.transform = self
+            .transforms
+            .choose(&mut self.rng)
+            .copied()
+            .unwrap_or_default();
+        let dated = schedule::dated_show(&self.dated_shows, Local::now().date_naive()).cloned();
+        self.dated_audio = dated.as_ref().and_then(|show| show.audio.clone());
+        let burst = dated.is_none() && self.burst_count > 1;
+        if let Some(show) = dated {
+            info!("showing {} for today", show.image.display());
+            for target in self.layer_surfaces.values_mut() {
+                target.show.image_paths = vec![show.image.clone()];
+            }
+        }
 
-        let stride = width * 4;
-        let size = stride * height;
-        self.pool.resize(size as usize).unwrap();
-        let (buffer, canvas) = self
-            .pool
-            .create_buffer(
-                width as i32,
-                height as i32,
-                stride as i32,
-                wayland_client::protocol::wl_shm::Format::Argb8888,
-            )
-            .expect("slotpool create_buffer failed");
-        let surface = layer.wl_surface();
-        surface.attach(Some(&buffer.wl_buffer()), 0, 0);
-        surface.damage_buffer(0, 0, width as i32, height as i32);
-        surface.commit();
+        let outputs: Vec<WlOutput> = self.layer_surfaces.keys().cloned().collect();
+        for output in outputs {
+            self.show_on(&output);
+        }
 
-        let img = ImageReader::open(self.image_path.as_ref().unwrap())
-            .unwrap()
-            .decode()
-            .unwrap()
-            .to_rgba8();
-        draw(canvas, self.width, self.height, img);
+        if burst {
+            self.schedule_burst();
+        }
 
-        let file = File::open(self.audio_path.as_ref().unwrap()).unwrap();
-        let source = Decoder::try_from(file).unwrap();
-        self.sink.append(source);
-        self.sink.play();
+        self.next_toggle = Instant::now() + self.next_timeout();
     }
-}
 
-impl ProvidesRegistryState for App {
-    fn registry(&mut self) -> &mut smithay_client_toolkit::registry::RegistryState {
-        &mut self.registry_state
+    /// Switches the images and audio over to a newly chosen pack, if packs are in use.
+    fn enter_pack(&mut self) {
+        let Some(dir) = &self.packs_dir else {
+            return;
+        };
+
+        match pack::choose(dir, self.pack.as_deref(), &self.ignore, &mut self.rng) {
+            Ok(pack) => {
+                info!("picked pack {}", pack.name);
+                self.images = MediaDir::images(pack.path.clone()).with_files(pack.images);
+                self.music = MediaDir::audio(pack.path).with_files(pack.audio);
+            }
+            Err(e) => warn!("sticking with the last pack: {e:#}"),
+        }
     }
 
-    registry_handlers![OutputState];
-}
+    /// Picks new images every `burst_frame` until `burst_count` groups have been up, the last
+    /// of which stays up for the rest of the show. The first group is already up.
+    fn schedule_burst(&mut self) {
+        let show_id = self.show_id;
+        let mut step = 0;
 
-impl CompositorHandler for App {
-    fn scale_factor_changed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _new_factor: i32,
-    ) {
+        let timer = self.loop_handle.insert_source(
+            Timer::from_duration(self.burst_frame),
+            move |_, _, app| {
+                step += 1;
+
+                if step >= app.burst_count || !app.shown || app.show_id != show_id {
+                    return TimeoutAction::Drop;
+                }
+
+                // rendering picks each group the same way a show does, per output if asked to
+                for target in app.layer_surfaces.values_mut() {
+                    target.show.last_image = std::mem::take(&mut target.show.image_paths).pop();
+                    target.show.overlay = None;
+                    target.transition = None;
+                }
+                for output in app.mapped_outputs() {
+                    app.render(&output);
+                }
+
+                if step + 1 < app.burst_count {
+                    TimeoutAction::ToDuration(app.burst_frame)
+                } else {
+                    TimeoutAction::Drop
+                }
+            },
+        );
+
+        if let Err(e) = timer {
+            error!("couldn't schedule the burst: {e}");
+        }
     }
 
-    fn transform_changed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _new_transform: wayland_client::protocol::wl_output::Transform,
-    ) {
+    /// Keeps the overlays that are still up around to crossfade from. Returns whether there
+    /// were any.
+    fn start_transitions(&mut self) -> bool {
+        if self.transition != Transition::Crossfade {
+            return false;
+        }
+
+        let opacity = self.opacity();
+        let mut any = false;
+
+        for target in self.layer_surfaces.values_mut() {
+            if !target.mapped {
+                continue;
+            }
+
+            if let Some(overlay) = target.show.overlay.take() {
+                target.transition = Some(Crossfade {
+                    from: overlay,
+                    opacity,
+                    start: Instant::now(),
+                });
+                any = true;
+            }
+        }
+
+        any
     }
 
-    fn frame(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _time: u32,
-    ) {
+    /// Shows the overlay on `output` right away if the compositor already told us its size,
+    /// otherwise asks for a configure and leaves the drawing to that.
+    fn show_on(&mut self, output: &WlOutput) {
+        let Some(target) = self.layer_surfaces.get(output) else {
+            return;
+        };
+        if !self.shows_on(output) {
+            return;
+        }
+
+        if target.configured {
+            if self.render(output) {
+                self.start_audio(output);
+                self.report_show(output);
+            }
+        } else {
+            let (width, height) = self.requested_size();
+            target.layer.set_size(width, height);
+            target.layer.commit();
+        }
     }
 
-    fn surface_enter(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _output: &wayland_client::protocol::wl_output::WlOutput,
-    ) {
+    /// Unmaps every surface.
+    fn detach_surfaces(&mut self) {
+        for target in self.layer_surfaces.values_mut() {
+            target.detach();
+        }
     }
 
-    fn surface_leave(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _output: &wayland_client::protocol::wl_output::WlOutput,
-    ) {
+    /// Whether the current show goes on `output`, which is all of them unless it was limited
+    /// to one.
+    fn shows_on(&self, output: &WlOutput) -> bool {
+        self.shown_only_on
+            .as_ref()
+            .is_none_or(|only| only == output)
     }
-}
 
-smithay_client_toolkit::delegate_output!(App);
-smithay_client_toolkit::delegate_layer!(App);
-smithay_client_toolkit::delegate_registry!(App);
-smithay_client_toolkit::delegate_shm!(App);
-smithay_client_toolkit::delegate_compositor!(App);
+    fn mapped_outputs(&self) -> Vec<WlOutput> {
+        self.layer_surfaces
+            .iter()
+            .filter(|(_, target)| target.mapped)
+            .map(|(output, _)| output.clone())
+            .collect()
+    }
 
-fn draw(canvas: &mut [u8], width: u32, height: u32, image: ImageBuffer<Rgba<u8>, Vec<u8>>) {
-    let img_width = image.width() as usize;
-    let img_height = image.height() as usize;
-    let img_pixels = image.into_raw();
+    /// Overlay opacity for the current point of the fade, if any.
+    fn opacity(&self) -> f32 {
+        let eased = |fade: &VisualFade| self.fade_easing.apply(fade.progress(self.fade_duration));
 
-    for px in canvas.chunks_exact_mut(4) {
-        px[0] = 128;
-        px[1] = 128;
-        px[2] = 128;
-        px[3] = 196;
+        match &self.visual_fade {
+            Some(fade) if fade.showing => eased(fade),
+            Some(fade) => 1.0 - eased(fade),
+            None => 1.0,
+        }
     }
 
-    let offset_x = ((width as usize) - img_width) / 2;
-    let offset_y = ((height as usize) - img_height) / 2;
+    fn fading(&self) -> bool {
+        self.visual_fade
+            .as_ref()
+            .is_some_and(|fade| fade.progress(self.fade_duration) < 1.0)
+    }
 
-    for y in 0..img_height {
-        for x in 0..img_width {
-            let src_i = (y * img_width + x) * 4;
+    /// Picks the audio for the current show and starts decoding it, unless another output
+    /// already did. `output` is the one that was just drawn, its image decides the pairing.
+    fn start_audio(&mut self, output: &WlOutput) {
+        if self.audio_path.is_some() {
+            return;
+        }
 
-            let dst_x = offset_x + x;
-            let dst_y = offset_y + y;
+        if self.sounds.is_none()
+            || self.muted
+            || self.silent
+            || schedule::is_quiet(&self.mute_hours, Local::now().time())
+        {
+            self.audio_unavailable();
+            return;
+        }
 
-            if dst_x >= width as usize || dst_y >= height as usize {
-                continue;
+        let image_path = self
+            .layer_surfaces
+            .get(output)
+            .and_then(|target| target.show.image_paths.first());
+
+        let planned = self
+            .dated_audio
+            .clone()
+            .map(|audio| vec![audio])
+            .or_else(|| image_path.and_then(|img| self.pairs.get(img)).cloned());
+
+        if let Some(paused) = self.paused_audio.take() {
+            let finished = self
+                .sounds
+                .as_ref()
+                .is_none_or(|sounds| sounds.current().empty());
+
+            if resumes(&paused, finished, planned.as_deref()) {
+                self.resume_audio(paused);
+                return;
             }
 
-            let dst_i = (dst_y * width as usize + dst_x) * 4;
+            // this image comes with its own clip
+            self.cancel_fade();
+            self.stop_audio();
+        }
 
-            let sr = img_pixels[src_i + 0] as f32;
-            let sg = img_pixels[src_i + 1] as f32;
-            let sb = img_pixels[src_i + 2] as f32;
-            let sa = img_pixels[src_i + 3] as f32 / 255.0;
+        let clips = match planned {
+            Some(planned) => planned,
+            None => match self.music.pick(
+                &self.ignore,
+                &mut self.audio_picker,
+                self.last_audio.as_slice(),
+                &mut self.rng,
+            ) {
+                Ok(path) => vec![path],
+                Err(e) => {
+                    warn!("playing without audio: {e:#}");
+                    self.audio_unavailable();
+                    return;
+                }
+            },
+        };
 
-            if sa == 0.0 {
-                continue;
+        self.audio_path = clips.first().cloned();
+        self.decode_audio(clips, self.pan_for(output));
+    }
+
+    /// Draws the current image onto the surface for `output` at its logical size and scale.
+    /// Returns false if there was nothing to draw.
+    fn render(&mut self, output: &WlOutput) -> bool {
+        let Some(target) = self.layer_surfaces.get(output) else {
+            return false;
+        };
+
+        let scale = target.scale.max(1);
+        let layout = match BufferLayout::new(target.width, target.height, scale) {
+            Ok(layout) => layout,
+            Err(e) => {
+                error!("not drawing: {e:#}");
+                return false;
             }
+        };
+        let (width, height) = (layout.width, layout.height);
 
-            let dr = canvas[dst_i + 2] as f32;
-            let dg = canvas[dst_i + 1] as f32;
-            let db = canvas[dst_i + 0] as f32;
-            let da = canvas[dst_i + 3] as f32 / 255.0;
+        // animation frames reuse the scaled image instead of scaling it again every frame
+        let cached = target
+            .show
+            .overlay
+            .as_ref()
+            .is_some_and(|overlay| overlay.reusable(self.show_id, (width, height)));
 
-            let out_a = sa + da * (1.0 - sa);
-            let out_r = (sr * sa + dr * da * (1.0 - sa)) / out_a;
-            let out_g = (sg * sa + dg * da * (1.0 - sa)) / out_a;
-            let out_b = (sb * sa + db * da * (1.0 - sa)) / out_a;
+        let format = match self.pixel_format() {
+            Ok(format) => format,
+            Err(e) => {
+                error!("not drawing: {e:#}");
+                return false;
+            }
+        };
+
+        if !cached {
+            if Channels::of(format).opaque {
+                debug!("drawing in {format:?}, the background and fades can't be see-through");
+            }
+
+            let pictures = self.choose_images(output);
+            if pictures.is_empty() {
+                return false;
+            }
+
+            let paths = self
+                .layer_surfaces
+                .get(output)
+                .map(|target| target.show.image_paths.clone())
+                .unwrap_or_default();
+            let (columns, rows) = grid_shape(pictures.len());
+            let cell = ((width / columns).max(1), (height / rows).max(1));
+            let sprites =
+                pictures
+                    .iter()
+                    .enumerate()
+                    .map(|(i, picture)| {
+                        let frames: Vec<Image> = picture
+                            .frames
+                            .iter()
+                            .map(|frame| {
+                                let frame = transform_image(frame, self.transform);
+                                let mut frame = scale_image(
+                                    &frame,
+                                    self.scale_mode,
+                                    self.size_limits,
+                                    self.scale_filter,
+                                    cell.0,
+                                    cell.1,
+                                )
+                                .into_owned();
+                                style_image(&mut frame, self.image_opacity, self.image_tint);
+                                frame
+                            })
+                            .collect();
+                        let anchor = paths
+                            .get(i)
+                            .map(|path| read_anchor(path))
+                            .unwrap_or_default();
+                        let position = place(
+                            self.placement,
+                            anchor,
+                            i,
+                            columns,
+                            cell,
+                            (width, height),
+                            frames[0].dimensions(),
+                            &mut self.rng,
+                        );
+                        let bounce = (self.animation == Animation::Bounce).then(|| {
+                            Bounce::new(
+                                (width, height),
+                                frames[0].dimensions(),
+                                position,
+                                self.bounce_speed * scale as f32,
+                                &mut self.rng,
+                            )
+                        });
+
+                        let caption = self.captioner.as_ref().zip(paths.get(i)).and_then(
+                            |(captioner, path)| {
+                                captioner.render(&caption_for(path), scale as f32, width)
+                            },
+                        );
+
+                        Sprite {
+                            frames,
+                            delays: picture.delays.clone(),
+                            position,
+                            bounce,
+                            caption,
+                        }
+                    })
+                    .collect();
+
+            if let Some(target) = self.layer_surfaces.get_mut(output) {
+                target.show.overlay = Some(Overlay {
+                    show_id: self.show_id,
+                    size: (width, height),
+                    sprites,
+                    backdrop: paint_backdrop(width, height, self.background, self.backdrop, format),
+                    format,
+                    letterbox: self.letterbox,
+                    scale: scale as f32,
+                    started: Instant::now(),
+                    frame_timer: false,
+                });
+            }
+        }
+
+        let opacity = self.opacity();
+        let animating = self.fading() || self.animation != Animation::None;
+        let show_id = self.show_id;
+
+        // the buffer that's up already shows all of this, committing it again would just make
+        // the compositor redraw
+        if let Some(target) = self.layer_surfaces.get(output)
+            && cached
+            && target.mapped
+            && !target.dirty
+            && target.drawn_opacity == opacity
+            && target.transition.is_none()
+            && self.animation == Animation::None
+        {
+            return true;
+        }
+
+        let transform = self
+            .layer_surfaces
+            .get(output)
+            .map_or(Transform::Normal, |target| target.transform);
+        // a quarter turn swaps the buffer's sides, everything is still drawn the surface's way up
+        let (buffer_width, buffer_height, stride) = if swaps_sides(transform) {
+            (height, width, height as i32 * 4)
+        } else {
+            (width, height, layout.stride)
+        };
+
+        if let Err(e) = self.pool.resize(layout.size) {
+            error!("couldn't grow the shm pool to {} bytes: {e}", layout.size);
+            return false;
+        }
+        let (buffer, buffer_canvas) =
+            match self
+                .pool
+                .create_buffer(buffer_width as i32, buffer_height as i32, stride, format)
+            {
+                Ok(created) => created,
+                Err(e) => {
+                    error!("couldn't create a {width}x{height} buffer: {e}");
+                    return false;
+                }
+            };
+
+        let mut upright = (transform != Transform::Normal).then(|| vec![0; layout.size]);
+        let canvas: &mut [u8] = match &mut upright {
+            Some(upright) => upright,
+            None => &mut *buffer_canvas,
+        };
+
+        let Some(target) = self.layer_surfaces.get_mut(output) else {
+            return false;
+        };
+        // anything that changes the whole canvas, rather than just where the images are, needs
+        // all of it damaged
+        let full_damage = !cached
+            || !target.mapped
+            || target.drawn_opacity != opacity
+            || target.transition.is_some();
+        let mut next_frame = None;
+        let mut drawn = None;
+        if let Some(overlay) = &mut target.show.overlay {
+            let (until_next, region) = overlay.paint(canvas);
+            drawn = region;
 
-            canvas[dst_i + 2] = out_r as u8;
-            canvas[dst_i + 1] = out_g as u8;
-            canvas[dst_i + 0] = out_b as u8;
-            canvas[dst_i + 3] = (out_a * 255.0) as u8;
+            if let Some(until_next) = until_next
+                && !overlay.frame_timer
+            {
+                overlay.frame_timer = true;
+                next_frame = Some(until_next);
+            }
+        }
+        let damage = match (full_damage, target.drawn_region, drawn) {
+            (false, Some(before), Some(now)) => before.union(now),
+            (false, Some(before), None) => before,
+            _ => Region::whole(width, height),
+        }
+        .transformed(width, height, transform);
+
+        let mut crossfading = false;
+        if let Some(transition) = &mut target.transition {
+            let mix = (transition.start.elapsed().as_secs_f32()
+                / self.transition_duration.as_secs_f32())
+            .min(1.0);
+
+            // an output that changed size since can't blend the old buffer, it just cuts over
+            if mix >= 1.0 || transition.from.size != (width, height) {
+                target.transition = None;
+            } else {
+                let mut previous = vec![0; canvas.len()];
+                transition.from.paint(&mut previous);
+                apply_opacity(&mut previous, transition.opacity);
+                crossfade(canvas, &previous, mix);
+                crossfading = true;
+            }
+        }
+        apply_opacity(canvas, opacity);
+
+        if let Some(upright) = &upright {
+            transform_pixels(upright, width, height, transform, buffer_canvas);
+        }
+
+        let surface = target.layer.wl_surface();
+
+        if animating || crossfading {
+            surface.frame(&self.qh, surface.clone());
+        }
+
+        surface.set_buffer_scale(scale);
+        surface.set_buffer_transform(transform);
+        surface.attach(Some(buffer.wl_buffer()), 0, 0);
+        surface.damage_buffer(
+            damage.x as i32,
+            damage.y as i32,
+            damage.width as i32,
+            damage.height as i32,
+        );
+        surface.commit();
+
+        if let Some(target) = self.layer_surfaces.get_mut(output) {
+            target.mapped = true;
+            target.dirty = false;
+            target.drawn_opacity = opacity;
+            target.drawn_region = drawn;
+        }
+
+        if let Some(delay) = next_frame {
+            self.schedule_frame(output.clone(), show_id, delay);
         }
+
+        true
     }
-}
 
-fn random_image() -> PathBuf {
-    let mut rng = rng();
-    let file_paths: Vec<PathBuf> = std::fs::read_dir("images")
-        .unwrap()
-        .map(|e| e.unwrap())
-        .map(|e| e.path())
-        .collect();
+    /// Redraws `output` once the current frame of an animated image is up.
+    fn schedule_frame(&mut self, output: WlOutput, show_id: u64, delay: Duration) {
+        let timer =
+            self.loop_handle
+                .insert_source(Timer::from_duration(delay), move |_, _, app| {
+                    let Some(target) = app.layer_surfaces.get_mut(&output) else {
+                        return TimeoutAction::Drop;
+                    };
+                    let Some(overlay) = &mut target.show.overlay else {
+                        return TimeoutAction::Drop;
+                    };
 
-    let i = rng.next_u32() as usize % file_paths.len();
-    file_paths[i].clone()
-}
+                    // the frame callback may have drawn the next frame already, that's fine
+                    overlay.frame_timer = false;
 
-fn random_audio() -> PathBuf {
-    let mut rng = rng();
-    let file_paths: Vec<PathBuf> = std::fs::read_dir("music")
-        .unwrap()
-        .map(|e| e.unwrap())
-        .map(|e| e.path())
-        .collect();
+                    if overlay.show_id == show_id && target.mapped {
+                        target.dirty = true;
+                        app.render(&output);
+                    }
 
-    let i = rng.next_u32() as usize % file_paths.len();
-    file_paths[i].clone()
+                    TimeoutAction::Drop
+                });
+
+        if let Err(e) = timer {
+            error!("couldn't schedule the next animation frame: {e}");
+        }
+    }
+
+    /// The best of [`PIXEL_FORMATS`] the compositor supports.
+    fn pixel_format(&self) -> Result<Format> {
+        let format = PIXEL_FORMATS
+            .iter()
+            .copied()
+            .find(|format| self.shm.formats().contains(format))
+            .ok_or_else(|| {
+                anyhow!(
+                    "the compositor supports none of the shm formats phonk-wl draws in ({:?})",
+                    PIXEL_FORMATS
+                )
+            })?;
+
+        Ok(format)
+    }
+
+    /// The size to ask the compositor for, (0, 0) lets a fullscreen surface span the output.
+    fn requested_size(&self) -> (u32, u32) {
+        match self.position {
+            Position::Fullscreen => (0, 0),
+            _ => self.size,
+        }
+    }
+
+    fn output_for(&self, surface: &WlSurface) -> Option<WlOutput> {
+        self.layer_surfaces
+            .iter()
+            .find(|(_, target)| target.layer.wl_surface() == surface)
+            .map(|(output, _)| output.clone())
+    }
+
+    /// Returns the images for the current show on `output`, picking and decoding them if needed.
+    /// Images that fail to decode are skipped in favour of another pick.
+    fn choose_images(&mut self, output: &WlOutput) -> Vec<Arc<Picture>> {
+        let Some(target) = self.layer_surfaces.get_mut(output) else {
+            return Vec::new();
+        };
+        // a collage doesn't repeat its images, nor the one the last show ended on
+        let mut exclude: Vec<PathBuf> = target.show.last_image.iter().cloned().collect();
+        let mut picked = std::mem::take(&mut target.show.image_paths);
+
+        // the other outputs already picked this show's images unless they pick their own
+        if picked.is_empty() && !self.per_output_images {
+            picked = self
+                .layer_surfaces
+                .values()
+                .find(|target| !target.show.image_paths.is_empty())
+                .map(|target| target.show.image_paths.clone())
+                .unwrap_or_default();
+        }
+
+        let mut picked = picked.into_iter();
+        let mut paths = Vec::new();
+        let mut pictures = Vec::new();
+        let mut failures = 0;
+
+        while pictures.len() < self.images_per_show && failures < MAX_IMAGE_ATTEMPTS {
+            let path = match picked.next() {
+                Some(path) => path,
+                None => match self.images.pick(
+                    &self.ignore,
+                    &mut self.image_picker,
+                    &exclude,
+                    &mut self.rng,
+                ) {
+                    Ok(path) => {
+                        info!("picked image {}", path.display());
+                        path
+                    }
+                    Err(e) => {
+                        if pictures.is_empty() {
+                            warn!("not showing overlay: {e:#}");
+                        }
+                        break;
+                    }
+                },
+            };
+
+            let max_pixels = self.max_image_pixels;
+            match self
+                .image_cache
+                .get_or_load(&path, |path| load_image(path, max_pixels))
+            {
+                Ok(img) => {
+                    pictures.push(img);
+                    paths.push(path.clone());
+                }
+                Err(e) => {
+                    warn!("skipping {}: {e:#}", path.display());
+                    failures += 1;
+                }
+            }
+            exclude.push(path);
+        }
+
+        if pictures.is_empty() && failures == MAX_IMAGE_ATTEMPTS {
+            warn!("not showing overlay: no image could be decoded");
+        }
+
+        if let Some(target) = self.layer_surfaces.get_mut(output) {
+            target.show.image_paths = paths;
+        }
+
+        pictures
+    }
+
+    /// Decodes `clips` on a worker thread, chained to play one after the other and panned by
+    /// `pan`. They're played once they're ready if the overlay is still showing by then.
+    fn decode_audio(&self, clips: Vec<PathBuf>, pan: f32) {
+        for clip in &clips {
+            info!("picked audio {}", clip.display());
+        }
+        let sender = self.decoded_audio.clone();
+        let show_id = self.show_id;
+        let normalize = self.normalize;
+        let known_gains: Vec<Option<f32>> = clips
+            .iter()
+            .map(|clip| self.normalized_gains.get(clip).copied())
+            .collect();
+
+        thread::spawn(move || {
+            let mut sources = Vec::with_capacity(clips.len());
+            let mut normalized_gains = Vec::new();
+            let mut failure = None;
+
+            for (clip, known_gain) in clips.iter().zip(known_gains) {
+                match decode_clip(clip, normalize, known_gain) {
+                    Ok((source, normalized_gain)) => {
+                        sources.push(source);
+                        if let Some(gain) = normalized_gain {
+                            normalized_gains.push((clip.clone(), gain));
+                        }
+                    }
+                    Err(e) if clips.len() > 1 => {
+                        warn!("leaving {} out of the sequence: {e:#}", clip.display());
+                        failure = Some(e);
+                    }
+                    Err(e) => failure = Some(e),
+                }
+            }
+
+            let source = match (sources.len(), failure) {
+                (0, Some(e)) => Err(e),
+                (0, None) => Err(anyhow!("no clips to play")),
+                (1, _) => Ok(sources.remove(0)),
+                _ => Ok(Box::new(rodio::source::from_iter(sources)) as AudioSource),
+            };
+            let source = source.map(|source| {
+                if pan == 0.0 {
+                    source
+                } else {
+                    Box::new(ChannelVolume::new(source, sound::pan_volumes(pan))) as AudioSource
+                }
+            });
+
+            let _ = sender.send(DecodedAudio {
+                show_id,
+                path: clips.into_iter().next().unwrap_or_default(),
+                source,
+                normalized_gains,
+            });
+        });
+    }
+
+    fn play_decoded(&mut self, decoded: DecodedAudio) {
+        if !self.shown || decoded.show_id != self.show_id {
+            return;
+        }
+
+        if self.muted {
+            self.audio_unavailable();
+            return;
+        }
+
+        let source = match decoded.source {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("couldn't decode {}: {e:#}", decoded.path.display());
+                self.audio_unavailable();
+                return;
+            }
+        };
+        let Some(busy) = self.sounds.as_ref().map(|sounds| !sounds.current().empty()) else {
+            return;
+        };
+        // the clips come with their gains applied
+        let target = self.volume;
+        self.normalized_gains.extend(decoded.normalized_gains);
+
+        // a clip still playing, or fading out, from an earlier show
+        if busy {
+            // a looping clip never finishes, so queueing behind one would never play
+            let policy = match self.audio_overlap {
+                AudioOverlapPolicy::Queue if self.loop_audio => AudioOverlapPolicy::Replace,
+                policy => policy,
+            };
+
+            match policy {
+                AudioOverlapPolicy::Replace => {
+                    self.cancel_fade();
+                    self.stop_audio();
+                }
+                AudioOverlapPolicy::Queue => {
+                    // let it finish at whatever volume it's at, the fade in below picks up from
+                    // there instead of muting it
+                    self.cancel_fade();
+                }
+                AudioOverlapPolicy::Mix => {
+                    // the fade belongs to the clip that's playing, the new one gets its own
+                    self.finish_fade();
+                    if let Some(sounds) = &mut self.sounds {
+                        sounds.add();
+                    }
+                }
+                AudioOverlapPolicy::Ignore => {
+                    debug!(
+                        "not playing {}, the last clip is still playing",
+                        decoded.path.display()
+                    );
+                    return;
+                }
+            }
+        }
+
+        let source = if self.loop_audio {
+            Box::new(source.repeat_infinite())
+        } else {
+            source
+        };
+
+        if let Some(sink) = self.sounds.as_ref().map(SoundPool::current) {
+            if sink.empty() {
+                sink.set_volume(0.0);
+            }
+            sink.append(source);
+            sink.play();
+        }
+        self.audio_playing = true;
+        self.fade_volume(target, self.fade_in, FadeEnd::Play);
+    }
+
+    /// Picks the clip paused by the last hide back up where it was.
+    fn resume_audio(&mut self, path: PathBuf) {
+        info!("resuming audio {}", path.display());
+        let target = self.volume;
+
+        // the clips that were mixed in under it stay behind
+        if let Some(sounds) = &mut self.sounds {
+            sounds.stop_older();
+            sounds.current().play();
+        }
+        self.audio_path = Some(path);
+        self.audio_playing = true;
+        self.fade_volume(target, self.fade_in, FadeEnd::Play);
+    }
+
+    /// Reports the show once the first output is drawn, with the image drawn there.
+    fn report_show(&mut self, output: &WlOutput) {
+        if self.reported_show == self.show_id {
+            return;
+        }
+
+        self.reported_show = self.show_id;
+        let image = self
+            .layer_surfaces
+            .get(output)
+            .and_then(|target| target.show.image_paths.first())
+            .cloned();
+        self.report(&Status::Show {
+            image,
+            audio: self.audio_path.clone(),
+        });
+    }
+
+    /// Prints `status` with `--status-json` and runs its hook.
+    fn report(&mut self, status: &Status) {
+        if self.status_json
+            && let Err(e) = status::print(status)
+        {
+            warn!("no longer printing statuses: {e:#}");
+            self.status_json = false;
+        }
+        self.hooks.run(status);
+    }
+
+    /// Without a clip to follow, the overlay falls back to the fixed duration.
+    fn audio_unavailable(&mut self) {
+        if self.follow_audio {
+            self.next_toggle = self.next_toggle.min(Instant::now() + self.visible_duration);
+        }
+    }
+
+    /// Ramps the sink volume to `target` over `duration`, then does `end` to the playback.
+    /// Replaces any fade already in progress.
+    fn fade_volume(&mut self, target: f32, duration: Duration, end: FadeEnd) {
+        let Some(from) = self.sounds.as_ref().map(|sounds| sounds.current().volume()) else {
+            return;
+        };
+
+        // fading out quiets everything that's playing, fading in only the new clip
+        let all_sinks = !matches!(end, FadeEnd::Play);
+
+        if duration.is_zero() {
+            self.cancel_fade();
+            self.set_volume(target, end, all_sinks);
+            return;
+        }
+
+        self.fade = Some(Fade {
+            from,
+            to: target,
+            start: Instant::now(),
+            duration,
+            end,
+            all_sinks,
+        });
+
+        if self.fade_timer.is_none() {
+            let token = self
+                .loop_handle
+                .insert_source(Timer::immediate(), |_, _, app| app.step_fade());
+
+            match token {
+                Ok(token) => self.fade_timer = Some(token),
+                Err(e) => {
+                    error!("couldn't start audio fade: {e}");
+                    self.fade = None;
+                    self.set_volume(target, end, all_sinks);
+                }
+            }
+        }
+    }
+
+    fn step_fade(&mut self) -> TimeoutAction {
+        let Some(fade) = &self.fade else {
+            self.fade_timer = None;
+            return TimeoutAction::Drop;
+        };
+
+        let t = (fade.start.elapsed().as_secs_f32() / fade.duration.as_secs_f32()).min(1.0);
+        let volume = fade.from + (fade.to - fade.from) * self.fade_easing.apply(t);
+        let end = if t >= 1.0 { fade.end } else { FadeEnd::Play };
+        self.set_volume(volume, end, fade.all_sinks);
+
+        if t < 1.0 {
+            return TimeoutAction::ToDuration(FADE_STEP);
+        }
+
+        self.fade = None;
+        self.fade_timer = None;
+        TimeoutAction::Drop
+    }
+
+    /// Sets the current clip's volume, or every clip's with `all_sinks`, then does `end` to the
+    /// playback.
+    fn set_volume(&self, volume: f32, end: FadeEnd, all_sinks: bool) {
+        if let Some(sounds) = &self.sounds {
+            if all_sinks {
+                sounds.set_volume_all(volume);
+            } else {
+                sounds.current().set_volume(volume);
+            }
+
+            match end {
+                FadeEnd::Play => {}
+                FadeEnd::Pause => sounds.pause_all(),
+                FadeEnd::Stop => sounds.stop_all(),
+            }
+        }
+    }
+
+    fn stop_audio(&self) {
+        if let Some(sounds) = &self.sounds {
+            sounds.stop_all();
+        }
+    }
+
+    /// Jumps to the end of the fade in progress, if any.
+    fn finish_fade(&mut self) {
+        if let Some(fade) = self.fade.take() {
+            self.set_volume(fade.to, fade.end, fade.all_sinks);
+        }
+        self.cancel_fade();
+    }
+
+    fn cancel_fade(&mut self) {
+        self.fade = None;
+
+        if let Some(token) = self.fade_timer.take() {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    /// Whether the overlay should be shown on `output`, going by the `outputs` config option.
+    fn wants_output(&self, output: &WlOutput) -> bool {
+        if self.outputs.is_empty() {
+            return true;
+        }
+
+        let Some(info) = self.output_state.info(output) else {
+            return false;
+        };
+
+        self.outputs.iter().any(|wanted| {
+            info.name.as_deref() == Some(wanted.as_str())
+                || info.description.as_deref() == Some(wanted.as_str())
+        })
+    }
+
+    /// Limits the next show to the output with this name or description, the ones after it go
+    /// back to every output.
+    pub fn set_target_output(&mut self, name: String) {
+        info!("showing the next overlay on {name} only");
+        self.target_output = Some(name);
+    }
+
+    /// The output with `name` as its name or description, if the overlay is shown on it.
+    fn output_named(&self, name: &str) -> Option<WlOutput> {
+        self.layer_surfaces
+            .keys()
+            .find(|output| {
+                self.output_state.info(output).is_some_and(|info| {
+                    info.name.as_deref() == Some(name) || info.description.as_deref() == Some(name)
+                })
+            })
+            .cloned()
+    }
+
+    /// Moves the overlay to `layer`, recreating every surface on it. An overlay that's up is
+    /// drawn again once the new surfaces are configured.
+    pub fn set_layer(&mut self, layer: OverlayLayer) {
+        if layer == self.layer {
+            return;
+        }
+
+        info!("moving the overlay to the {layer:?} layer");
+        self.layer = layer;
+        let qh = self.qh.clone();
+        let outputs: Vec<WlOutput> = self.layer_surfaces.keys().cloned().collect();
+
+        for output in outputs {
+            // dropping the old surface destroys it, the new one goes on with the same images
+            let show = self
+                .layer_surfaces
+                .remove(&output)
+                .map(|target| target.show)
+                .unwrap_or_default();
+            self.create_layer_surface(&qh, output.clone(), show);
+
+            if self.shown {
+                self.show_on(&output);
+            }
+        }
+    }
+
+    /// Creates the layer surface the overlay is drawn on for `output`, showing `show`.
+    fn create_layer_surface(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: WlOutput,
+        show: SurfaceShow,
+    ) {
+        let surface = self.compositor_state.create_surface(qh);
+        let layer_surface = self.layer_shell.create_layer_surface(
+            qh,
+            surface,
+            layer_for(self.layer),
+            Some("rbar"),
+            Some(&output),
+        );
+
+        // while an overlay is up, the first configure draws it on this output too
+        let (width, height) = self.requested_size();
+        layer_surface.set_anchor(anchor_for(self.position));
+        layer_surface.set_margin(self.margin, self.margin, self.margin, self.margin);
+        // asking for keyboard focus only when it's needed, so the overlay doesn't steal it
+        layer_surface.set_keyboard_interactivity(if self.dismiss_on_key {
+            KeyboardInteractivity::OnDemand
+        } else {
+            KeyboardInteractivity::None
+        });
+        layer_surface.set_size(width, height);
+        layer_surface.set_exclusive_zone(self.exclusive_zone);
+        layer_surface.commit();
+
+        let scale = self
+            .output_state
+            .info(&output)
+            .map_or(1, |info| info.scale_factor);
+        self.layer_surfaces.insert(
+            output,
+            OutputSurface {
+                layer: layer_surface,
+                width: 0,
+                height: 0,
+                scale,
+                configured: false,
+                mapped: false,
+                dirty: false,
+                drawn_opacity: 0.0,
+                drawn_region: None,
+                transform: Transform::Normal,
+                show,
+                transition: None,
+            },
+        );
+    }
+
+    /// Where the audio of a show started on `output` sits in the stereo field, going by
+    /// `output_pan`.
+    fn pan_for(&self, output: &WlOutput) -> f32 {
+        let Some(info) = self.output_state.info(output) else {
+            return 0.0;
+        };
+
+        [info.name, info.description]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.output_pan.get(&key).copied())
+            .unwrap_or(0.0)
+    }
+
+    /// The output's name for log messages.
+    fn output_name(&self, output: &WlOutput) -> String {
+        self.output_state
+            .info(output)
+            .and_then(|info| info.name)
+            .unwrap_or_else(|| "(unnamed)".to_string())
+    }
+
+    /// Silences the audio until unmuted, overlays keep showing. Muting stops the clip that's
+    /// playing, unmuting leaves the sound to the next show.
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted == self.muted {
+            return;
+        }
+
+        info!("{}", if muted { "muted" } else { "unmuted" });
+        self.muted = muted;
+
+        if muted {
+            self.cancel_fade();
+            self.stop_audio();
+            self.paused_audio = None;
+            // the clip ending like this shouldn't hide an overlay that follows it
+            if self.shown && self.audio_playing {
+                self.audio_playing = false;
+                self.audio_unavailable();
+            }
+        }
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.set_muted(!self.muted);
+    }
+
+    /// Drops cached images and the last picks, so edits to the media directories take effect.
+    pub fn reload_media(&mut self) {
+        self.image_cache.clear();
+        self.images.reload();
+        self.music.reload();
+        self.normalized_gains.clear();
+        self.image_picker.reset();
+        self.audio_picker.reset();
+        for target in self.layer_surfaces.values_mut() {
+            target.show.last_image = None;
+        }
+        self.last_audio = None;
+    }
+
+    /// Records a key press or click on `seat`, returns whether that's enough to hide the
+    /// overlay under `dismiss_seat`.
+    fn dismissed_from(&mut self, seat: WlSeat) -> bool {
+        let name = self.seat_state.info(&seat).and_then(|info| info.name);
+
+        match &self.dismiss_seat {
+            DismissSeat::Any => true,
+            DismissSeat::Named(wanted) => name.as_ref() == Some(wanted),
+            DismissSeat::All => {
+                if !self.dismissed_by.contains(&seat) {
+                    self.dismissed_by.push(seat);
+                }
+
+                let keyboard_seats = self.keyboards.iter().map(|(s, _)| s);
+                let pointer_seats = self.pointers.iter().map(|(s, _)| s);
+                let done = keyboard_seats
+                    .chain(pointer_seats)
+                    .all(|s| self.dismissed_by.contains(s));
+                if !done {
+                    debug!("waiting for the other seats to dismiss the overlay");
+                }
+                done
+            }
+        }
+    }
+
+    /// Draws the current frame of the overlay on every output it's up on again and saves it as
+    /// an RGBA PNG, to see what the compositor was handed.
+    pub fn snapshot(&mut self) -> Result<()> {
+        let outputs = self.mapped_outputs();
+        if outputs.is_empty() {
+            bail!("nothing to snapshot, the overlay isn't up");
+        }
+
+        for output in outputs {
+            let name = self.output_name(&output);
+            let path = PathBuf::from(
+                self.snapshot_path
+                    .to_string_lossy()
+                    .replace("{output}", &name),
+            );
+            let Some(overlay) = self
+                .layer_surfaces
+                .get_mut(&output)
+                .and_then(|target| target.show.overlay.as_mut())
+            else {
+                continue;
+            };
+
+            let (width, height) = overlay.size;
+            let mut canvas = vec![0; width as usize * height as usize * 4];
+            overlay.paint(&mut canvas);
+
+            RgbaImage::from_raw(width, height, to_rgba(canvas, overlay.format))
+                .ok_or_else(|| anyhow!("canvas doesn't match {width}x{height}"))?
+                .save(&path)
+                .with_context(|| format!("saving {}", path.display()))?;
+            info!("saved the overlay on {name} to {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Prints a status line to stdout on every show and hide.
+    pub fn print_status(&mut self) {
+        self.status_json = true;
+    }
+
+    /// Keeps the media directory listings between picks, only safe once they're watched.
+    pub fn watching_media(&mut self) {
+        self.images.watched = true;
+        self.music.watched = true;
+    }
+
+    /// Called by the watcher when files were added to or removed from the media directories.
+    pub fn media_changed(&mut self) {
+        self.images.invalidate();
+        self.music.invalidate();
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    /// Stops audio and destroys every layer surface, leaving nothing behind on the compositor.
+    pub fn shutdown(&mut self) {
+        self.cancel_fade();
+        self.stop_audio();
+        self.detach_surfaces();
+        self.layer_surfaces.clear();
+
+        if self.shown {
+            self.report(&Status::Hide);
+        }
+        self.shown = false;
+    }
+
+    /// Returns true with odds of `chance`, from 0.0 to 1.0.
+    pub fn roll(&mut self, chance: f64) -> bool {
+        self.rng.random_bool(chance)
+    }
+
+    /// Lets a scheduled show pass, the next one is due after another interval.
+    pub fn skip_show(&mut self) {
+        debug!("skipping this show");
+        self.next_toggle = Instant::now() + self.interval();
+    }
+
+    /// `show_interval` give or take up to `jitter`.
+    fn interval(&mut self) -> Duration {
+        jittered(self.show_interval, self.jitter, &mut self.rng)
+    }
+
+    /// When the timer should toggle the overlay next.
+    pub fn next_toggle(&self) -> Instant {
+        self.next_toggle
+    }
+
+    /// Whether another show would go over `max_shows_per_minute`.
+    pub fn throttled(&mut self) -> bool {
+        let Some(max) = self.max_shows_per_minute else {
+            return false;
+        };
+
+        let throttled = over_rate(&mut self.recent_shows, Instant::now(), max);
+        if throttled {
+            info!("throttled, {max} shows in the last minute already");
+        }
+        throttled
+    }
+
+    /// When the cooldown since the last show ends, if it hasn't yet.
+    pub fn cooldown_until(&self) -> Option<Instant> {
+        self.last_show
+            .map(|last_show| last_show + self.cooldown)
+            .filter(|until| *until > Instant::now())
+    }
+
+    /// When the timer should run next, sooner than [`Self::next_toggle`] while it has to watch
+    /// for the end of the audio.
+    pub fn next_wakeup(&self) -> Instant {
+        if self.shown && self.follow_audio {
+            self.next_toggle.min(Instant::now() + AUDIO_POLL)
+        } else {
+            self.next_toggle
+        }
+    }
+
+    /// Whether the overlay follows the audio and the clip for this show has ended.
+    pub fn audio_finished(&self) -> bool {
+        self.shown
+            && self.follow_audio
+            && self.audio_playing
+            && self
+                .sounds
+                .as_ref()
+                .is_some_and(|sounds| sounds.current().empty())
+    }
+
+    /// How long to wait before the next toggle: the overlay stays up for `visible_duration`
+    /// (or until the audio ends) and stays hidden for `show_interval`.
+    fn next_timeout(&mut self) -> Duration {
+        if self.shown && self.follow_audio {
+            self.max_duration
+        } else if self.shown {
+            self.visible_duration
+        } else {
+            self.interval()
+        }
+    }
+}
+
+impl ShmHandler for App {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl OutputHandler for App {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, output: WlOutput) {
+        let name = self.output_name(&output);
+
+        if !self.wants_output(&output) {
+            debug!("skipping output {name}");
+            return;
+        }
+
+        info!("showing on output {name}");
+        self.create_layer_surface(qh, output, SurfaceShow::default());
+    }
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        // dropping the layer surface destroys it, a replugged output gets a fresh one in
+        // new_output
+        if self.layer_surfaces.remove(&output).is_some() {
+            info!("output {} removed", self.output_name(&output));
+
+            // nothing left to show the overlay on, so don't leave its audio playing either
+            if self.layer_surfaces.is_empty() {
+                self.hide();
+            }
+        }
+    }
+}
+
+impl LayerShellHandler for App {
+    fn closed(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        let Some(output) = self.output_for(layer.wl_surface()) else {
+            return;
+        };
+        let Some(closed) = self.layer_surfaces.remove(&output) else {
+            return;
+        };
+
+        let name = self.output_name(&output);
+        // one that never got a size was turned down outright, asking again would loop
+        if closed.width > 0 && self.output_state.info(&output).is_some() {
+            info!("overlay surface on {name} was closed, making a new one");
+            self.create_layer_surface(qh, output.clone(), SurfaceShow::default());
+            if self.shown {
+                self.show_on(&output);
+            }
+        } else {
+            info!("overlay surface on {name} was closed");
+        }
+
+        if self.layer_surfaces.is_empty() {
+            self.hide();
+        }
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let Some(output) = self.output_for(layer.wl_surface()) else {
+            return;
+        };
+
+        let Some((width, height)) = configured_size(configure.new_size, self.requested_size())
+        else {
+            debug!("waiting for a configure with a size before drawing");
+            return;
+        };
+
+        if let Some(target) = self.layer_surfaces.get_mut(&output) {
+            target.width = width;
+            target.height = height;
+            target.configured = true;
+        }
+
+        if std::mem::take(&mut self.show_pending) && !self.shown {
+            // draws on this output along with the others
+            self.show();
+            return;
+        }
+
+        if !self.shown || !self.shows_on(&output) {
+            return;
+        }
+
+        if self.render(&output) {
+            self.start_audio(&output);
+            self.report_show(&output);
+        }
+    }
+}
+
+impl ProvidesRegistryState for App {
+    fn registry(&mut self) -> &mut smithay_client_toolkit::registry::RegistryState {
+        &mut self.registry_state
+    }
+
+    registry_handlers![OutputState, SeatState];
+}
+
+impl SeatHandler for App {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Keyboard
+            && self.dismiss_on_key
+            && !self.keyboards.iter().any(|(s, _)| *s == seat)
+        {
+            match self.seat_state.get_keyboard(qh, &seat, None) {
+                Ok(keyboard) => self.keyboards.push((seat.clone(), keyboard)),
+                Err(e) => warn!("dismissing with a key won't work: {e}"),
+            }
+        }
+
+        if capability == Capability::Pointer
+            && self.dismiss_on_click
+            && !self.pointers.iter().any(|(s, _)| *s == seat)
+        {
+            match self.seat_state.get_pointer(qh, &seat) {
+                Ok(pointer) => self.pointers.push((seat, pointer)),
+                Err(e) => warn!("dismissing with a click won't work: {e}"),
+            }
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Keyboard {
+            self.keyboards.retain(|(s, keyboard)| {
+                let keep = *s != seat;
+                if !keep {
+                    keyboard.release();
+                }
+                keep
+            });
+        }
+
+        if capability == Capability::Pointer {
+            self.pointers.retain(|(s, pointer)| {
+                let keep = *s != seat;
+                if !keep {
+                    pointer.release();
+                }
+                keep
+            });
+        }
+    }
+
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, seat: WlSeat) {
+        self.dismissed_by.retain(|s| *s != seat);
+    }
+}
+
+impl PointerHandler for App {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        if !events
+            .iter()
+            .any(|event| matches!(event.kind, PointerEventKind::Press { .. }))
+        {
+            return;
+        }
+
+        let seat = self
+            .pointers
+            .iter()
+            .find(|(_, p)| p == pointer)
+            .map(|(seat, _)| seat.clone());
+        if let Some(seat) = seat
+            && self.dismissed_from(seat)
+        {
+            debug!("dismissed with a click");
+            self.hide();
+        }
+    }
+}
+
+impl KeyboardHandler for App {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+        _raw: &[u32],
+        _keysyms: &[Keysym],
+    ) {
+    }
+
+    fn leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+    ) {
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        let seat = self
+            .keyboards
+            .iter()
+            .find(|(_, k)| k == keyboard)
+            .map(|(seat, _)| seat.clone());
+        if let Some(seat) = seat
+            && self.dismissed_from(seat)
+        {
+            debug!("dismissed with {:?}", event.keysym);
+            self.hide();
+        }
+    }
+
+    fn repeat_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn release_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _modifiers: Modifiers,
+        _raw_modifiers: RawModifiers,
+        _layout: u32,
+    ) {
+    }
+}
+
+impl CompositorHandler for App {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        new_factor: i32,
+    ) {
+        let Some(output) = self.output_for(surface) else {
+            return;
+        };
+
+        if let Some(target) = self.layer_surfaces.get_mut(&output) {
+            target.scale = new_factor;
+        }
+
+        if self
+            .layer_surfaces
+            .get(&output)
+            .is_some_and(|target| target.mapped)
+        {
+            self.render(&output);
+        }
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        new_transform: Transform,
+    ) {
+        let Some(output) = self.output_for(surface) else {
+            return;
+        };
+
+        debug!(
+            "{} prefers buffers in {new_transform:?}",
+            self.output_name(&output)
+        );
+        if let Some(target) = self.layer_surfaces.get_mut(&output) {
+            target.transform = new_transform;
+            target.dirty = true;
+            target.drawn_region = None;
+        }
+
+        if self
+            .layer_surfaces
+            .get(&output)
+            .is_some_and(|target| target.mapped)
+        {
+            self.render(&output);
+        }
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        _time: u32,
+    ) {
+        let Some(output) = self.output_for(surface) else {
+            return;
+        };
+
+        if let Some(fade) = &self.visual_fade
+            && !fade.showing
+            && fade.progress(self.fade_duration) >= 1.0
+        {
+            self.visual_fade = None;
+            self.detach_surfaces();
+            return;
+        }
+
+        if self
+            .layer_surfaces
+            .get(&output)
+            .is_some_and(|target| target.mapped)
+        {
+            self.render(&output);
+        }
+    }
+
+    fn surface_enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wayland_client::protocol::wl_surface::WlSurface,
+        _output: &wayland_client::protocol::wl_output::WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wayland_client::protocol::wl_surface::WlSurface,
+        _output: &wayland_client::protocol::wl_output::WlOutput,
+    ) {
+    }
+}
+
+/// The overlay's layer surface on one output.
+struct OutputSurface {
+    layer: LayerSurface,
+    /// Logical size from the last configure.
+    width: u32,
+    height: u32,
+    scale: i32,
+    /// Whether the last configure still applies, it's invalidated by unmapping the surface.
+    configured: bool,
+    /// Whether a buffer is attached.
+    mapped: bool,
+    /// Whether the attached buffer is out of date, like when an animation moved on to its next
+    /// frame.
+    dirty: bool,
+    /// Opacity the attached buffer was drawn at.
+    drawn_opacity: f32,
+    /// Where the images are in the attached buffer, the next frame only damages that and where
+    /// they've moved to. `None` damages the whole buffer.
+    drawn_region: Option<Region>,
+    /// How the compositor would like buffers rotated for this output, the overlay is drawn
+    /// upright and then turned to match.
+    transform: Transform,
+    show: SurfaceShow,
+    transition: Option<Crossfade>,
+}
+
+/// What a surface shows, carried over to the new surface when one is recreated mid-show.
+#[derive(Default)]
+struct SurfaceShow {
+    /// The images picked for the current show.
+    image_paths: Vec<PathBuf>,
+    /// The last image of the previous show, not picked again right away.
+    last_image: Option<PathBuf>,
+    overlay: Option<Overlay>,
+}
+
+impl OutputSurface {
+    /// Unmaps the surface. An unmapped layer surface needs a fresh configure before it can be
+    /// shown again.
+    fn detach(&mut self) {
+        let surface = self.layer.wl_surface();
+        surface.attach(None, 0, 0);
+        surface.commit();
+        self.mapped = false;
+        self.configured = false;
+        self.show.overlay = None;
+        self.transition = None;
+    }
+}
+
+/// The overlay of the last show, blending into the current one.
+struct Crossfade {
+    from: Overlay,
+    /// What `from` was drawn at when it was replaced, it's faded out from there.
+    opacity: f32,
+    start: Instant,
+}
+
+/// Buffer dimensions for a surface, in physical pixels.
+#[derive(Debug, PartialEq, Eq)]
+struct BufferLayout {
+    width: u32,
+    height: u32,
+    stride: i32,
+    size: usize,
+}
+
+impl BufferLayout {
+    /// Fails instead of overflowing for sizes too large to draw.
+    fn new(logical_width: u32, logical_height: u32, scale: i32) -> Result<Self> {
+        let too_large =
+            || anyhow!("{logical_width}x{logical_height} at scale {scale} is too large");
+        let scale = u32::try_from(scale).map_err(|_| too_large())?;
+
+        let width = logical_width.checked_mul(scale).ok_or_else(too_large)?;
+        let height = logical_height.checked_mul(scale).ok_or_else(too_large)?;
+        if width > MAX_BUFFER_DIMENSION || height > MAX_BUFFER_DIMENSION {
+            return Err(too_large());
+        }
+
+        let stride = width
+            .checked_mul(4)
+            .and_then(|stride| i32::try_from(stride).ok())
+            .ok_or_else(too_large)?;
+        let size = (stride as usize)
+            .checked_mul(height as usize)
+            .ok_or_else(too_large)?;
+
+        Ok(Self {
+            width,
+            height,
+            stride,
+            size,
+        })
+    }
+}
+
+/// The overlay image for one show, scaled to the buffer size.
+struct Overlay {
+    show_id: u64,
+    size: (u32, u32),
+    sprites: Vec<Sprite>,
+    /// What goes behind the images, as a ready to copy buffer.
+    backdrop: Vec<u8>,
+    letterbox: Option<Rgba<u8>>,
+    /// How `backdrop` and the buffers drawn from this are laid out.
+    format: Format,
+    /// Buffer pixels per logical pixel.
+    scale: f32,
+    started: Instant,
+    /// Whether a timer is already waiting to draw the next frame.
+    frame_timer: bool,
+}
+
+/// One of the images on an overlay.
+struct Sprite {
+    /// A single frame unless the image is animated.
+    frames: Vec<Image>,
+    delays: Vec<Duration>,
+    /// Top left corner, unless it's bouncing around.
+    position: (isize, isize),
+    bounce: Option<Bounce>,
+    /// Drawn under the image, with `show_caption`.
+    caption: Option<Image>,
+}
+
+impl Overlay {
+    /// Whether this is what show `show_id` draws on a `size` canvas, so it can be drawn again
+    /// without scaling the images again.
+    fn reusable(&self, show_id: u64, size: (u32, u32)) -> bool {
+        self.show_id == show_id && self.size == size
+    }
+
+    /// Draws the current frames onto `canvas`, returning how long until the next one if any
+    /// image is animated and the region the images and captions cover.
+    fn paint(&mut self, canvas: &mut [u8]) -> (Option<Duration>, Option<Region>) {
+        let elapsed = self.started.elapsed();
+        let mut until_next: Option<Duration> = None;
+        let mut frames = Vec::with_capacity(self.sprites.len());
+
+        for sprite in &mut self.sprites {
+            let (index, next) = frame_at(&sprite.delays, elapsed);
+            until_next = match (until_next, next) {
+                (Some(current), Some(next)) => Some(current.min(next)),
+                (current, next) => current.or(next),
+            };
+
+            let position = match &mut sprite.bounce {
+                Some(bounce) => bounce.advance(),
+                None => sprite.position,
+            };
+            frames.push((index, position));
+        }
+
+        let layers: Vec<(&Image, (isize, isize))> = self
+            .sprites
+            .iter()
+            .zip(frames)
+            .map(|(sprite, (index, position))| (&sprite.frames[index], position))
+            .collect();
+        let mut region = draw(
+            canvas,
+            self.size.0,
+            self.size.1,
+            &self.backdrop,
+            self.letterbox,
+            &layers,
+            self.format,
+        );
+
+        for (sprite, (image, position)) in self.sprites.iter().zip(&layers) {
+            if let Some(caption) = &sprite.caption {
+                let (x, y) = caption_position(
+                    self.size,
+                    *position,
+                    image.dimensions(),
+                    caption.dimensions(),
+                    self.scale,
+                );
+                blend(canvas, self.size.0, self.size.1, caption, x, y, self.format);
+                region = Region::merge(
+                    region,
+                    Region::clipped((x, y), caption.dimensions(), self.size),
+                );
+            }
+        }
+
+        (until_next, region)
+    }
+}
+
+/// A rectangle of canvas pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Region {
+    fn whole(width: u32, height: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// The part of a `size` rectangle at `offset` that's on a `canvas` sized canvas, `None` if
+    /// it's entirely off it.
+    fn clipped(offset: (isize, isize), size: (u32, u32), canvas: (u32, u32)) -> Option<Self> {
+        let clip = |offset: isize, len: u32, bound: u32| {
+            let start = offset.clamp(0, bound as isize) as u32;
+            let end = (offset + len as isize).clamp(0, bound as isize) as u32;
+            (start, end)
+        };
+        let (left, right) = clip(offset.0, size.0, canvas.0);
+        let (top, bottom) = clip(offset.1, size.1, canvas.1);
+
+        (left < right && top < bottom).then_some(Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    /// The bounding box of both.
+    fn union(self, other: Self) -> Self {
+        let (left, top) = (self.x.min(other.x), self.y.min(other.y));
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    fn merge(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.union(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Where these pixels of a `width`x`height` upright canvas end up in a buffer turned by
+    /// `transform`, the way `transform_pixels` moves them.
+    fn transformed(self, width: u32, height: u32, transform: Transform) -> Self {
+        let flipped = matches!(
+            transform,
+            Transform::Flipped
+                | Transform::Flipped90
+                | Transform::Flipped180
+                | Transform::Flipped270
+        );
+        // the columns after mirroring, as a start and an end
+        let (fx, fx_end) = if flipped {
+            (width - self.x - self.width, width - self.x)
+        } else {
+            (self.x, self.x + self.width)
+        };
+        let (y, y_end) = (self.y, self.y + self.height);
+
+        let ((x0, x1), (y0, y1)) = match transform {
+            Transform::_90 | Transform::Flipped90 => ((y, y_end), (width - fx_end, width - fx)),
+            Transform::_180 | Transform::Flipped180 => {
+                ((width - fx_end, width - fx), (height - y_end, height - y))
+            }
+            Transform::_270 | Transform::Flipped270 => ((height - y_end, height - y), (fx, fx_end)),
+            _ => ((fx, fx_end), (y, y_end)),
+        };
+
+        Self {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        }
+    }
+}
+
+/// The frame that's up `elapsed` into a looping animation, and how long until the next one.
+/// Still images always show their only frame.
+fn frame_at(delays: &[Duration], elapsed: Duration) -> (usize, Option<Duration>) {
+    let total: Duration = delays.iter().sum();
+
+    if delays.len() < 2 || total.is_zero() {
+        return (0, None);
+    }
+
+    let mut into_loop = Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64);
+
+    for (i, delay) in delays.iter().enumerate() {
+        if into_loop < *delay {
+            return (i, Some(*delay - into_loop));
+        }
+        into_loop -= *delay;
+    }
+
+    // unreachable since into_loop < total, but the first frame is always a safe answer
+    (0, Some(delays[0]))
+}
+
+/// Whether `max` shows already started in the minute before `now`, forgetting older ones.
+fn over_rate(recent: &mut VecDeque<Instant>, now: Instant, max: usize) -> bool {
+    while recent
+        .front()
+        .is_some_and(|start| now.duration_since(*start) >= Duration::from_secs(60))
+    {
+        recent.pop_front();
+    }
+
+    recent.len() >= max
+}
+
+/// A random duration within `jitter` of `base`, never shorter than [`MIN_INTERVAL`].
+fn jittered(base: Duration, jitter: Duration, rng: &mut impl RngCore) -> Duration {
+    if jitter.is_zero() {
+        return base;
+    }
+
+    let offset = rng.random_range(-jitter.as_secs_f64()..=jitter.as_secs_f64());
+    Duration::from_secs_f64((base.as_secs_f64() + offset).max(MIN_INTERVAL.as_secs_f64()))
+}
+
+/// Moves the image around the surface at a constant speed, reflecting off its edges.
+struct Bounce {
+    /// How far the image can move on each axis.
+    bounds: (f32, f32),
+    position: (f32, f32),
+    /// Pixels per second.
+    velocity: (f32, f32),
+    last: Instant,
+}
+
+impl Bounce {
+    fn new(
+        surface: (u32, u32),
+        image: (u32, u32),
+        start: (isize, isize),
+        speed: f32,
+        rng: &mut impl RngCore,
+    ) -> Self {
+        let bounds = (
+            surface.0 as f32 - image.0 as f32,
+            surface.1 as f32 - image.1 as f32,
+        );
+        // start heading diagonally, in a random direction
+        let axis_speed = speed / std::f32::consts::SQRT_2;
+        let direction = rng.next_u32();
+        let sign = |bit: u32| if direction & bit == 0 { 1.0 } else { -1.0 };
+
+        Self {
+            bounds,
+            position: (
+                (start.0 as f32).clamp(0.0, bounds.0.max(0.0)),
+                (start.1 as f32).clamp(0.0, bounds.1.max(0.0)),
+            ),
+            velocity: (axis_speed * sign(1), axis_speed * sign(2)),
+            last: Instant::now(),
+        }
+    }
+
+    /// Moves the image by the time since the last call and returns its top left corner.
+    fn advance(&mut self) -> (isize, isize) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f32();
+        self.last = now;
+
+        self.step(elapsed)
+    }
+
+    fn step(&mut self, elapsed: f32) -> (isize, isize) {
+        let (x, dx) = reflect(self.position.0, self.velocity.0, elapsed, self.bounds.0);
+        let (y, dy) = reflect(self.position.1, self.velocity.1, elapsed, self.bounds.1);
+        self.position = (x, y);
+        self.velocity = (dx, dy);
+
+        (x as isize, y as isize)
+    }
+}
+
+/// Moves `position` along one axis within `0..=bound`, flipping `velocity` at the edges. A
+/// negative bound means the image doesn't fit, it's kept centered then.
+fn reflect(position: f32, velocity: f32, elapsed: f32, bound: f32) -> (f32, f32) {
+    if bound <= 0.0 {
+        return (bound / 2.0, velocity);
+    }
+
+    // unfold the bounces into a line of length 2 * bound that's always walked forwards
+    let period = 2.0 * bound;
+    let unfolded = if velocity >= 0.0 {
+        position
+    } else {
+        period - position
+    };
+    let travelled = (unfolded + velocity.abs() * elapsed).rem_euclid(period);
+
+    if travelled <= bound {
+        (travelled, velocity.abs())
+    } else {
+        (period - travelled, -velocity.abs())
+    }
+}
+
+/// The overlay fading in or out, advanced by frame callbacks.
+struct VisualFade {
+    start: Instant,
+    showing: bool,
+}
+
+impl VisualFade {
+    fn progress(&self, duration: Duration) -> f32 {
+        if duration.is_zero() {
+            return 1.0;
+        }
+
+        (self.start.elapsed().as_secs_f32() / duration.as_secs_f32()).min(1.0)
+    }
+}
+
+type AudioSource = Box<dyn Source + Send>;
+
+struct DecodedAudio {
+    show_id: u64,
+    path: PathBuf,
+    source: Result<AudioSource>,
+    /// Computed on the worker thread with `normalize` for the clips that weren't analyzed yet.
+    normalized_gains: Vec<(PathBuf, f32)>,
+}
+
+struct Fade {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+    end: FadeEnd,
+    /// Whether the clips mixed in under the current one fade along, like when the overlay
+    /// hides.
+    all_sinks: bool,
+}
+
+/// What a finished fade does to the playback.
+#[derive(Clone, Copy)]
+enum FadeEnd {
+    Play,
+    Pause,
+    Stop,
+}
+
+smithay_client_toolkit::delegate_output!(App);
+smithay_client_toolkit::delegate_layer!(App);
+smithay_client_toolkit::delegate_registry!(App);
+smithay_client_toolkit::delegate_shm!(App);
+smithay_client_toolkit::delegate_compositor!(App);
+smithay_client_toolkit::delegate_seat!(App);
+smithay_client_toolkit::delegate_keyboard!(App);
+smithay_client_toolkit::delegate_pointer!(App);
+
+/// How a pixel is laid out in the shm formats drawn in. The 8888 formats are little endian, so
+/// Argb8888 is laid out b, g, r, a.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Channels {
+    /// Byte offsets of red, green, blue and alpha.
+    offsets: [usize; 4],
+    /// The compositor ignores the alpha byte, so nothing drawn can be see-through.
+    opaque: bool,
+}
+
+impl Channels {
+    fn of(format: Format) -> Self {
+        let opaque = matches!(format, Format::Xrgb8888 | Format::Xbgr8888);
+        let offsets = match format {
+            Format::Abgr8888 | Format::Xbgr8888 => [0, 1, 2, 3],
+            _ => [2, 1, 0, 3],
+        };
+
+        Self { offsets, opaque }
+    }
+
+    /// Writes `color` to `px` premultiplied, or fully opaque if the format can't be anything
+    /// else.
+    fn write(self, px: &mut [u8], Rgba(mut color): Rgba<u8>) {
+        if self.opaque {
+            color[3] = 255;
+        } else {
+            let alpha = color[3] as f32 / 255.0;
+            for channel in &mut color[..3] {
+                *channel = (*channel as f32 * alpha).round() as u8;
+            }
+        }
+
+        for (offset, value) in self.offsets.into_iter().zip(color) {
+            px[offset] = value;
+        }
+    }
+}
+
+/// The shm formats drawn in, best first. The ones with alpha keep the overlay see-through, and
+/// Argb8888 and Xrgb8888 are supported by every compositor.
+const PIXEL_FORMATS: &[Format] = &[
+    Format::Argb8888,
+    Format::Abgr8888,
+    Format::Xrgb8888,
+    Format::Xbgr8888,
+];
+
+/// Renders the fill behind the image, laid out for `format`.
+fn paint_backdrop(
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    backdrop: Backdrop,
+    format: Format,
+) -> Vec<u8> {
+    let channels = Channels::of(format);
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+    let center = (width as f32 / 2.0, height as f32 / 2.0);
+    let max_distance = (center.0 * center.0 + center.1 * center.1).sqrt().max(1.0);
+
+    for (i, px) in pixels.chunks_exact_mut(4).enumerate() {
+        let x = (i % width as usize) as f32 + 0.5;
+        let y = (i / width as usize) as f32 + 0.5;
+
+        let color = match backdrop {
+            Backdrop::Solid => background,
+            Backdrop::VerticalGradient { bottom } => {
+                lerp_color(background, bottom, y / height.max(1) as f32)
+            }
+            Backdrop::Vignette { strength } => {
+                let dx = x - center.0;
+                let dy = y - center.1;
+                let distance = (dx * dx + dy * dy).sqrt() / max_distance;
+                let shade = 1.0 - strength.clamp(0.0, 1.0) * distance * distance;
+                let Rgba([r, g, b, a]) = background;
+                let darken = |c: u8| (c as f32 * shade) as u8;
+                Rgba([darken(r), darken(g), darken(b), a])
+            }
+        };
+
+        channels.write(px, color);
+    }
+
+    pixels
+}
+
+fn lerp_color(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let channel = |i: usize| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t) as u8;
+    Rgba([channel(0), channel(1), channel(2), channel(3)])
+}
+
+/// Washes the colors of `image` towards `tint`, as far as the tint's alpha says, and scales its
+/// alpha by `opacity`.
+fn style_image(image: &mut Image, opacity: f32, tint: Option<Rgba<u8>>) {
+    if opacity >= 1.0 && tint.is_none() {
+        return;
+    }
+
+    for pixel in image.pixels_mut() {
+        if let Some(tint) = tint {
+            let alpha = pixel[3];
+            *pixel = lerp_color(*pixel, tint, tint[3] as f32 / 255.0);
+            pixel[3] = alpha;
+        }
+        pixel[3] = (pixel[3] as f32 * opacity) as u8;
+    }
+}
+
+/// Draws the first frame of the image at `path` onto a `width` by `height` canvas the way a show
+/// with `config` would at scale 1, without a compositor. Returns the canvas as straight RGBA.
+pub fn render_still(config: &Config, path: &Path, width: u32, height: u32) -> Result<Vec<u8>> {
+    let picture = load_image(path, config.max_image_pixels)?;
+    let limits = SizeLimits {
+        min: config.min_image_size.map(f64::from),
+        max: config.max_image_size.map(f64::from),
+    };
+    let mut frame = scale_image(
+        &picture.frames[0],
+        config.scale,
+        limits,
+        filter_for(config.scale_filter),
+        width,
+        height,
+    )
+    .into_owned();
+    style_image(
+        &mut frame,
+        clamp_unit(config.image_opacity, "image_opacity"),
+        config.image_tint,
+    );
+    let position = read_anchor(path).offset((width, height), frame.dimensions());
+
+    let mut canvas = vec![0; width as usize * height as usize * 4];
+    draw(
+        &mut canvas,
+        width,
+        height,
+        &paint_backdrop(
+            width,
+            height,
+            config.background,
+            config.backdrop,
+            Format::Abgr8888,
+        ),
+        config.letterbox_color,
+        &[(&frame, position)],
+        Format::Abgr8888,
+    );
+
+    if let (true, Some(font)) = (config.show_caption, &config.caption_font) {
+        let captioner = Captioner::load(font, config.caption_size, config.caption_color)?;
+        if let Some(caption) = captioner.render(&caption_for(path), 1.0, width) {
+            let (x, y) = caption_position(
+                (width, height),
+                position,
+                frame.dimensions(),
+                caption.dimensions(),
+                1.0,
+            );
+            blend(&mut canvas, width, height, &caption, x, y, Format::Abgr8888);
+        }
+    }
+
+    unpremultiply(&mut canvas);
+    Ok(canvas)
+}
+
+/// Fills `canvas` with `backdrop` and composites each image over it at its offset, in order,
+/// with the channels laid out for `format`. Whatever falls outside the canvas is cropped. With a
+/// `letterbox` color, only the areas behind the images get the backdrop and the rest of the
+/// canvas gets that color. Returns the bounding box of the images, everything outside it is
+/// plain backdrop or letterbox.
+fn draw(
+    canvas: &mut [u8],
+    width: u32,
+    height: u32,
+    backdrop: &[u8],
+    letterbox: Option<Rgba<u8>>,
+    images: &[(&Image, (isize, isize))],
+    format: Format,
+) -> Option<Region> {
+    match letterbox {
+        None => canvas.copy_from_slice(backdrop),
+        Some(color) => {
+            for px in canvas.chunks_exact_mut(4) {
+                Channels::of(format).write(px, color);
+            }
+            for (image, offset) in images {
+                copy_rect(canvas, backdrop, width, height, image.dimensions(), *offset);
+            }
+        }
+    }
+
+    let mut region = None;
+    for (image, (offset_x, offset_y)) in images {
+        blend(canvas, width, height, image, *offset_x, *offset_y, format);
+        region = Region::merge(
+            region,
+            Region::clipped((*offset_x, *offset_y), image.dimensions(), (width, height)),
+        );
+    }
+
+    region
+}
+
+/// Copies the `size` rectangle at `offset` from `source` to `canvas`, clipped to both.
+fn copy_rect(
+    canvas: &mut [u8],
+    source: &[u8],
+    width: u32,
+    height: u32,
+    size: (u32, u32),
+    offset: (isize, isize),
+) {
+    let clip = |offset: isize, len: u32, bound: u32| {
+        let start = offset.clamp(0, bound as isize) as usize;
+        let end = (offset + len as isize).clamp(0, bound as isize) as usize;
+        start..end
+    };
+    let columns = clip(offset.0, size.0, width);
+    let stride = width as usize * 4;
+
+    for y in clip(offset.1, size.1, height) {
+        let row = y * stride + columns.start * 4..y * stride + columns.end * 4;
+        canvas[row.clone()].copy_from_slice(&source[row]);
+    }
+}
+
+/// Blends `image` over `canvas`, laid out for `format`, with its top left corner at
+/// `(offset_x, offset_y)`. The canvas is premultiplied, like the compositor reads it.
+fn blend(
+    canvas: &mut [u8],
+    width: u32,
+    height: u32,
+    image: &Image,
+    offset_x: isize,
+    offset_y: isize,
+    format: Format,
+) {
+    let [r, g, b, a] = Channels::of(format).offsets;
+    let img_width = image.width() as usize;
+    let img_height = image.height() as usize;
+    let img_pixels = image.as_raw();
+
+    for y in 0..img_height {
+        for x in 0..img_width {
+            let src_i = (y * img_width + x) * 4;
+
+            let dst_x = offset_x + x as isize;
+            let dst_y = offset_y + y as isize;
+
+            if dst_x < 0 || dst_y < 0 || dst_x >= width as isize || dst_y >= height as isize {
+                continue;
+            }
+
+            let dst_i = (dst_y as usize * width as usize + dst_x as usize) * 4;
+
+            let sr = img_pixels[src_i] as f32;
+            let sg = img_pixels[src_i + 1] as f32;
+            let sb = img_pixels[src_i + 2] as f32;
+            let sa = img_pixels[src_i + 3] as f32 / 255.0;
+
+            if sa == 0.0 {
+                continue;
+            }
+
+            let dr = canvas[dst_i + r] as f32;
+            let dg = canvas[dst_i + g] as f32;
+            let db = canvas[dst_i + b] as f32;
+            let da = canvas[dst_i + a] as f32 / 255.0;
+
+            let out_a = sa + da * (1.0 - sa);
+            let out_r = sr * sa + dr * (1.0 - sa);
+            let out_g = sg * sa + dg * (1.0 - sa);
+            let out_b = sb * sa + db * (1.0 - sa);
+
+            // rounded, truncating would darken the result by up to a step per blend
+            canvas[dst_i + r] = out_r.round() as u8;
+            canvas[dst_i + g] = out_g.round() as u8;
+            canvas[dst_i + b] = out_b.round() as u8;
+            canvas[dst_i + a] = (out_a * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Whether `transform` turns a buffer by a quarter, making it as wide as the surface is tall.
+fn swaps_sides(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// Copies the `width` by `height` pixels of `upright` into `buffer` with `transform` applied: a
+/// flip around the vertical axis for the flipped ones, then a counter-clockwise rotation.
+fn transform_pixels(
+    upright: &[u8],
+    width: u32,
+    height: u32,
+    transform: Transform,
+    buffer: &mut [u8],
+) {
+    let (w, h) = (width as usize, height as usize);
+    let flipped = matches!(
+        transform,
+        Transform::Flipped | Transform::Flipped90 | Transform::Flipped180 | Transform::Flipped270
+    );
+    let buffer_width = if swaps_sides(transform) { h } else { w };
+
+    for y in 0..h {
+        for x in 0..w {
+            let fx = if flipped { w - 1 - x } else { x };
+            let (bx, by) = match transform {
+                Transform::_90 | Transform::Flipped90 => (y, w - 1 - fx),
+                Transform::_180 | Transform::Flipped180 => (w - 1 - fx, h - 1 - y),
+                Transform::_270 | Transform::Flipped270 => (h - 1 - y, fx),
+                _ => (fx, y),
+            };
+
+            let from = (y * w + x) * 4;
+            let to = (by * buffer_width + bx) * 4;
+            buffer[to..to + 4].copy_from_slice(&upright[from..from + 4]);
+        }
+    }
+}
+
+/// Blends `previous` into `canvas`, `mix` going from 0 (all `previous`) to 1 (all `canvas`).
+fn crossfade(canvas: &mut [u8], previous: &[u8], mix: f32) {
+    for (channel, previous) in canvas.iter_mut().zip(previous) {
+        *channel = (*previous as f32 + (*channel as f32 - *previous as f32) * mix) as u8;
+    }
+}
+
+/// Offset that centers `image` on `surface`. Images bigger than the surface get negative offsets
+/// and are cropped evenly on both sides.
+fn centered(surface: (u32, u32), image: (u32, u32)) -> (isize, isize) {
+    (
+        (surface.0 as isize - image.0 as isize) / 2,
+        (surface.1 as isize - image.1 as isize) / 2,
+    )
+}
+
+/// Columns and rows of the smallest grid, about as wide as it's tall, with a cell for each of
+/// `count` images.
+fn grid_shape(count: usize) -> (u32, u32) {
+    let count = count.max(1) as u32;
+    let columns = count.isqrt() + u32::from(count.isqrt().pow(2) < count);
+    (columns, count.div_ceil(columns))
+}
+
+/// Where an image sits in its cell, read from a `<image>.anchor` sidecar like `top-left`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ImageAnchor {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl ImageAnchor {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "center" => Self::Center,
+            "top" => Self::Top,
+            "bottom" => Self::Bottom,
+            "left" => Self::Left,
+            "right" => Self::Right,
+            "top-left" => Self::TopLeft,
+            "top-right" => Self::TopRight,
+            "bottom-left" => Self::BottomLeft,
+            "bottom-right" => Self::BottomRight,
+            _ => return None,
+        })
+    }
+
+    /// Offset that puts `image` at this spot of `area`. Images bigger than the area are cropped
+    /// on the side away from the anchor, or evenly on both when it's centered on that axis.
+    fn offset(self, area: (u32, u32), image: (u32, u32)) -> (isize, isize) {
+        let (center_x, center_y) = centered(area, image);
+        let right = area.0 as isize - image.0 as isize;
+        let bottom = area.1 as isize - image.1 as isize;
+
+        let x = match self {
+            Self::Left | Self::TopLeft | Self::BottomLeft => 0,
+            Self::Right | Self::TopRight | Self::BottomRight => right,
+            _ => center_x,
+        };
+        let y = match self {
+            Self::Top | Self::TopLeft | Self::TopRight => 0,
+            Self::Bottom | Self::BottomLeft | Self::BottomRight => bottom,
+            _ => center_y,
+        };
+
+        (x, y)
+    }
+}
+
+/// Reads the anchor from a `<image>.anchor` sidecar file next to the image, centered if there's
+/// none.
+fn read_anchor(image_path: &Path) -> ImageAnchor {
+    let mut sidecar = image_path.as_os_str().to_owned();
+    sidecar.push(".anchor");
+    let sidecar = PathBuf::from(sidecar);
+
+    let Ok(contents) = std::fs::read_to_string(&sidecar) else {
+        return ImageAnchor::default();
+    };
+
+    ImageAnchor::parse(contents.trim()).unwrap_or_else(|| {
+        warn!(
+            "ignoring {}: unknown anchor {:?}",
+            sidecar.display(),
+            contents.trim()
+        );
+        ImageAnchor::default()
+    })
+}
+
+/// Top left corner for the `index`th image of an overlay laid out in `columns` of `cell`s, at
+/// `anchor` of its cell. Randomly placed images may run off the edges, but always keep at least
+/// half of them on it.
+#[allow(clippy::too_many_arguments)]
+fn place(
+    placement: Placement,
+    anchor: ImageAnchor,
+    index: usize,
+    columns: u32,
+    cell: (u32, u32),
+    surface: (u32, u32),
+    image: (u32, u32),
+    rng: &mut impl RngCore,
+) -> (isize, isize) {
+    match placement {
+        Placement::Grid => {
+            let (x, y) = anchor.offset(cell, image);
+            let column = index as u32 % columns;
+            let row = index as u32 / columns;
+            (x + (column * cell.0) as isize, y + (row * cell.1) as isize)
+        }
+        Placement::Random => {
+            let mut axis = |surface: u32, image: u32| {
+                let overhang = i64::from(image / 2);
+                rng.random_range(-overhang..=i64::from(surface) - overhang) as isize
+            };
+            (axis(surface.0, image.0), axis(surface.1, image.1))
+        }
+    }
+}
+
+/// Reorders a canvas laid out for `format` to straight RGBA, with the alpha of formats that
+/// don't have any made opaque like the compositor would.
+fn to_rgba(mut canvas: Vec<u8>, format: Format) -> Vec<u8> {
+    let channels = Channels::of(format);
+
+    for px in canvas.chunks_exact_mut(4) {
+        let [r, g, b, a] = channels.offsets.map(|offset| px[offset]);
+        px.copy_from_slice(&[r, g, b, if channels.opaque { 255 } else { a }]);
+    }
+    unpremultiply(&mut canvas);
+
+    canvas
+}
+
+/// Turns premultiplied RGBA back into the straight alpha image files expect.
+fn unpremultiply(rgba: &mut [u8]) {
+    for px in rgba.chunks_exact_mut(4) {
+        let alpha = px[3];
+        if alpha == 0 || alpha == 255 {
+            continue;
+        }
+
+        for channel in &mut px[..3] {
+            *channel = (*channel as f32 * 255.0 / alpha as f32).round().min(255.0) as u8;
+        }
+    }
+}
+
+/// Fades the canvas by scaling every channel, the compositor reads shm buffers as premultiplied.
+fn apply_opacity(canvas: &mut [u8], opacity: f32) {
+    if opacity >= 1.0 {
+        return;
+    }
+
+    for channel in canvas.iter_mut() {
+        *channel = (*channel as f32 * opacity) as u8;
+    }
+}
+
+/// The size to draw at for a configure of `new_size`, `None` until the size is known. A zero
+/// dimension means we get to pick, so it sticks with what was `requested`, but a fullscreen
+/// surface requests none and has to wait for the compositor to fill it in.
+fn configured_size(new_size: (u32, u32), requested: (u32, u32)) -> Option<(u32, u32)> {
+    let pick = |new: u32, requested: u32| if new == 0 { requested } else { new };
+    let size = (pick(new_size.0, requested.0), pick(new_size.1, requested.1));
+
+    (size.0 > 0 && size.1 > 0).then_some(size)
+}
+
+fn filter_for(filter: ScaleFilter) -> FilterType {
+    match filter {
+        ScaleFilter::Nearest => FilterType::Nearest,
+        ScaleFilter::Triangle => FilterType::Triangle,
+        ScaleFilter::CatmullRom => FilterType::CatmullRom,
+        ScaleFilter::Gaussian => FilterType::Gaussian,
+        ScaleFilter::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+fn layer_for(layer: OverlayLayer) -> Layer {
+    match layer {
+        OverlayLayer::Background => Layer::Background,
+        OverlayLayer::Bottom => Layer::Bottom,
+        OverlayLayer::Top => Layer::Top,
+        OverlayLayer::Overlay => Layer::Overlay,
+    }
+}
+
+fn anchor_for(position: Position) -> Anchor {
+    match position {
+        Position::Fullscreen => Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM,
+        // no anchors centers the surface
+        Position::Center => Anchor::empty(),
+        Position::Top => Anchor::TOP,
+        Position::Bottom => Anchor::BOTTOM,
+        Position::Left => Anchor::LEFT,
+        Position::Right => Anchor::RIGHT,
+        Position::TopLeft => Anchor::TOP | Anchor::LEFT,
+        Position::TopRight => Anchor::TOP | Anchor::RIGHT,
+        Position::BottomLeft => Anchor::BOTTOM | Anchor::LEFT,
+        Position::BottomRight => Anchor::BOTTOM | Anchor::RIGHT,
+    }
+}
+
+pub fn load_image(path: &Path, max_pixels: u64) -> Result<Picture> {
+    if has_extension(path, &["jxl"]) {
+        return load_jxl(path, max_pixels);
+    }
+
+    let dimensions = ImageReader::open(path)?
+        .into_dimensions()
+        .with_context(|| format!("reading the size of {}", path.display()))?;
+    check_pixels(dimensions, max_pixels)?;
+
+    if has_extension(path, &["gif"]) {
+        return load_gif(path);
+    }
+
+    let image = ImageReader::open(path)?
+        .decode()
+        .with_context(|| format!("decoding {}", path.display()))?;
+
+    Ok(Picture::still(image.to_rgba8()))
+}
+
+/// Opens an audio file and reads enough of it to know how to decode the rest.
+pub fn load_audio(path: &Path) -> Result<Decoder<BufReader<File>>> {
+    Ok(Decoder::try_from(File::open(path)?)?)
+}
+
+/// Fails for images with more than `max_pixels` pixels, before they're decoded.
+fn check_pixels((width, height): (u32, u32), max_pixels: u64) -> Result<()> {
+    let pixels = u64::from(width) * u64::from(height);
+
+    if pixels > max_pixels {
+        bail!("{width}x{height} is more than max_image_pixels ({max_pixels} pixels)");
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "jxl")]
+fn load_jxl(path: &Path, max_pixels: u64) -> Result<Picture> {
+    use image::ImageDecoder;
+
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let decoder = jxl_oxide::integration::JxlDecoder::new(BufReader::new(file))
+        .with_context(|| format!("decoding {}", path.display()))?;
+    check_pixels(decoder.dimensions(), max_pixels)?;
+    let image = image::DynamicImage::from_decoder(decoder)
+        .with_context(|| format!("decoding {}", path.display()))?;
+
+    Ok(Picture::still(image.to_rgba8()))
+}
+
+#[cfg(not(feature = "jxl"))]
+fn load_jxl(path: &Path, _max_pixels: u64) -> Result<Picture> {
+    bail!(
+        "{} is a JPEG XL image, which needs phonk-wl built with --features jxl",
+        path.display()
+    )
+}
+
+/// Decodes every frame of a GIF, those with only one frame are treated like any still image.
+fn load_gif(path: &Path) -> Result<Picture> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let frames = GifDecoder::new(BufReader::new(file))
+        .and_then(|decoder| decoder.into_frames().collect_frames())
+        .with_context(|| format!("decoding {}", path.display()))?;
+
+    let (delays, frames): (Vec<Duration>, Vec<Image>) = frames
+        .into_iter()
+        .map(|frame| (gif_delay(frame.delay()), frame.into_buffer()))
+        .unzip();
+
+    match frames.len() {
+        0 => bail!("{} has no frames", path.display()),
+        1 => Ok(Picture::still(frames.into_iter().next().unwrap())),
+        _ => Ok(Picture { frames, delays }),
+    }
+}
+
+/// Browsers bump very short GIF delays up to 100ms since plenty of files rely on it, so do the
+/// same.
+fn gif_delay(delay: image::Delay) -> Duration {
+    let delay = Duration::from(delay);
+
+    if delay < Duration::from_millis(20) {
+        Duration::from_millis(100)
+    } else {
+        delay
+    }
+}
+
+fn transform_image(image: &Image, transform: ImageTransform) -> Cow<'_, Image> {
+    match transform {
+        ImageTransform::None => Cow::Borrowed(image),
+        ImageTransform::Rotate90 => Cow::Owned(imageops::rotate90(image)),
+        ImageTransform::Rotate180 => Cow::Owned(imageops::rotate180(image)),
+        ImageTransform::Rotate270 => Cow::Owned(imageops::rotate270(image)),
+        ImageTransform::FlipHorizontal => Cow::Owned(imageops::flip_horizontal(image)),
+        ImageTransform::FlipVertical => Cow::Owned(imageops::flip_vertical(image)),
+    }
+}
+
+/// Bounds on the drawn image size, as fractions of the largest size that fits the surface with
+/// the image's aspect ratio. They override the scale mode, except for `stretch`.
+#[derive(Debug, Clone, Copy, Default)]
+struct SizeLimits {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+fn scale_image<'a>(
+    image: &'a Image,
+    mode: ScaleMode,
+    limits: SizeLimits,
+    filter: FilterType,
+    width: u32,
+    height: u32,
+) -> Cow<'a, Image> {
+    let (img_width, img_height) = image.dimensions();
+
+    if width == 0 || height == 0 || img_width == 0 || img_height == 0 {
+        return Cow::Borrowed(image);
+    }
+
+    let width_ratio = width as f64 / img_width as f64;
+    let height_ratio = height as f64 / img_height as f64;
+    let fit_ratio = width_ratio.min(height_ratio);
+
+    let ratio = match mode {
+        ScaleMode::Stretch => {
+            return Cow::Owned(imageops::resize(image, width, height, filter));
+        }
+        ScaleMode::Center => 1.0,
+        ScaleMode::Fit => fit_ratio.min(1.0),
+        ScaleMode::Fill => width_ratio.max(height_ratio),
+    };
+    let ratio = ratio
+        .max(limits.min.map_or(0.0, |min| fit_ratio * min))
+        .min(limits.max.map_or(f64::INFINITY, |max| fit_ratio * max));
+
+    let (new_width, new_height) = scaled_dimensions(img_width, img_height, ratio);
+
+    if (new_width, new_height) == (img_width, img_height) {
+        return Cow::Borrowed(image);
+    }
+
+    Cow::Owned(imageops::resize(image, new_width, new_height, filter))
+}
+
+fn scaled_dimensions(width: u32, height: u32, ratio: f64) -> (u32, u32) {
+    let scaled_width = (width as f64 * ratio).round().max(1.0) as u32;
+    let scaled_height = (height as f64 * ratio).round().max(1.0) as u32;
+    (scaled_width, scaled_height)
+}
+
+fn clamp_unit(value: f32, what: &str) -> f32 {
+    let clamped = if value.is_nan() {
+        1.0
+    } else {
+        value.clamp(0.0, 1.0)
+    };
+
+    if clamped != value {
+        warn!("{what} {value} is outside 0.0..=1.0, using {clamped}");
+    }
+
+    clamped
+}
+
+/// RMS level clips are normalized to, about -20 dBFS.
+const TARGET_RMS: f32 = 0.1;
+
+/// Decodes the whole clip and works out the gain that brings it to [`TARGET_RMS`].
+fn normalize(decoder: Decoder<BufReader<File>>) -> (SamplesBuffer, f32) {
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<f32> = decoder.collect();
+    let gain = normalization_gain(&samples);
+
+    (SamplesBuffer::new(channels, sample_rate, samples), gain)
+}
+
+/// Gain that brings `samples` to [`TARGET_RMS`], held back enough that the loudest peak doesn't
+/// clip. Silence is left alone.
+fn normalization_gain(samples: &[f32]) -> f32 {
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    if samples.is_empty() || peak == 0.0 {
+        return 1.0;
+    }
+
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    let rms = mean_square.sqrt();
+
+    (TARGET_RMS / rms).min(1.0 / peak)
+}
+
+/// Opens a clip with its gain sidecar and normalization applied. With `normalize` and no
+/// `known_gain` yet, it's decoded upfront to work the gain out, which is returned too.
+fn decode_clip(
+    path: &Path,
+    normalize: bool,
+    known_gain: Option<f32>,
+) -> Result<(AudioSource, Option<f32>)> {
+    let decoder = load_audio(path)?;
+    let (source, normalized_gain) = if normalize && known_gain.is_none() {
+        let (buffer, gain) = self::normalize(decoder);
+        (Box::new(buffer) as AudioSource, Some(gain))
+    } else {
+        (Box::new(decoder) as AudioSource, None)
+    };
+    let gain = read_gain(path) * normalized_gain.or(known_gain).unwrap_or(1.0);
+
+    if gain == 1.0 {
+        return Ok((source, normalized_gain));
+    }
+
+    Ok((Box::new(source.amplify(gain)), normalized_gain))
+}
+
+/// Reads the per-track gain from a `<track>.gain` sidecar file next to the audio, if any.
+fn read_gain(audio_path: &Path) -> f32 {
+    let mut sidecar = audio_path.as_os_str().to_owned();
+    sidecar.push(".gain");
+    let sidecar = PathBuf::from(sidecar);
+
+    let Ok(contents) = std::fs::read_to_string(&sidecar) else {
+        return 1.0;
+    };
+
+    match contents.trim().parse::<f32>() {
+        Ok(gain) => clamp_unit(gain, &format!("gain in {}", sidecar.display())),
+        Err(e) => {
+            warn!("ignoring {}: {e}", sidecar.display());
+            1.0
+        }
+    }
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png",
+    "jpg",
+    "jpeg",
+    "webp",
+    "bmp",
+    "gif",
+    #[cfg(feature = "avif")]
+    "avif",
+    #[cfg(feature = "jxl")]
+    "jxl",
+];
+const AUDIO_EXTENSIONS: &[&str] = &["ogg", "oga", "mp3", "flac", "wav", "m4a", "mp4", "aac"];
+
+/// The supported files in a media directory, or listed in a playlist file. They're listed again
+/// on every pick unless the directory is watched, then the listing is kept until the watcher
+/// reports a change. A playlist is read once and kept until the media is reloaded.
+struct MediaDir {
+    path: PathBuf,
+    filter: fn(&Path) -> bool,
+    /// What the files are called in errors.
+    kind: &'static str,
+    /// `path` is a playlist file rather than a directory.
+    playlist: bool,
+    watched: bool,
+    files: Option<Vec<PathBuf>>,
+}
+
+impl MediaDir {
+    fn images(path: PathBuf) -> Self {
+        Self::new(path, is_supported_image, "images")
+    }
+
+    fn audio(path: PathBuf) -> Self {
+        Self::new(path, is_supported_audio, "audio files")
+    }
+
+    fn new(path: PathBuf, filter: fn(&Path) -> bool, kind: &'static str) -> Self {
+        Self {
+            path,
+            filter,
+            kind,
+            playlist: false,
+            watched: false,
+            files: None,
+        }
+    }
+
+    /// Reads the files from the playlist at `path` instead of listing a directory.
+    fn read_as_playlist(mut self) -> Self {
+        self.playlist = true;
+        self.read_playlist();
+        self
+    }
+
+    /// Starts out with `files` instead of listing the directory, a pack's for example.
+    fn with_files(mut self, files: Vec<PathBuf>) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    fn pick(
+        &mut self,
+        ignore: &[Pattern],
+        picker: &mut Picker,
+        avoid: &[PathBuf],
+        rng: &mut impl RngCore,
+    ) -> Result<PathBuf> {
+        let file_paths = match &self.files {
+            Some(files) => files.clone(),
+            None if self.playlist => {
+                let files = read_playlist(&self.path, self.filter)?;
+                self.files = Some(files.clone());
+                files
+            }
+            None => {
+                let files = list_files(&self.path, self.filter, ignore)?;
+                if self.watched {
+                    self.files = Some(files.clone());
+                }
+                files
+            }
+        };
+
+        picker
+            .pick(file_paths, avoid, rng)?
+            .ok_or_else(|| anyhow!("no {} in {}", self.kind, self.path.display()))
+    }
+
+    /// Drops the kept listing, the next pick lists the directory again. A playlist is kept.
+    fn invalidate(&mut self) {
+        if !self.playlist {
+            self.files = None;
+        }
+    }
+
+    /// Drops the kept listing, reading the playlist again right away if it is one.
+    fn reload(&mut self) {
+        self.files = None;
+        if self.playlist {
+            self.read_playlist();
+        }
+    }
+
+    /// Keeps the files listed in the playlist. If it can't be read, the next pick tries again.
+    fn read_playlist(&mut self) {
+        match read_playlist(&self.path, self.filter) {
+            Ok(files) => self.files = Some(files),
+            Err(e) => warn!("{e:#}"),
+        }
+    }
+}
+
+/// How the next file is chosen from a media directory.
+enum Picker {
+    /// Independent picks, only avoiding an immediate repeat.
+    Random,
+    /// Like `Random`, with each file's odds proportional to its weight. Unlisted files weigh 1.
+    Weighted(HashMap<PathBuf, u32>),
+    /// Every file once before any repeats.
+    Shuffle(Playlist),
+}
+
+impl Picker {
+    fn pick(
+        &mut self,
+        file_paths: Vec<PathBuf>,
+        avoid: &[PathBuf],
+        rng: &mut impl RngCore,
+    ) -> Result<Option<PathBuf>> {
+        match self {
+            Self::Random => Ok(pick_file(file_paths, avoid, rng)),
+            Self::Weighted(weights) => pick_weighted(file_paths, avoid, weights, rng),
+            Self::Shuffle(playlist) => Ok(playlist.next(file_paths, avoid, rng)),
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Self::Shuffle(playlist) = self {
+            playlist.clear();
+        }
+    }
+}
+
+/// Picks a random path, excluding the ones in `avoid` unless there's nothing else.
+fn pick_file(
+    file_paths: Vec<PathBuf>,
+    avoid: &[PathBuf],
+    rng: &mut impl RngCore,
+) -> Option<PathBuf> {
+    let mut file_paths = avoiding(file_paths, avoid);
+
+    if file_paths.is_empty() {
+        return None;
+    }
+
+    let i = rng.next_u32() as usize % file_paths.len();
+    Some(file_paths.swap_remove(i))
+}
+
+/// Like [`pick_file`], but files are picked in proportion to their weight in `weights`.
+/// Fails if there are files but all of them weigh 0.
+fn pick_weighted(
+    mut file_paths: Vec<PathBuf>,
+    avoid: &[PathBuf],
+    weights: &HashMap<PathBuf, u32>,
+    rng: &mut impl RngCore,
+) -> Result<Option<PathBuf>> {
+    let weight = |path: &PathBuf| weights.get(path).copied().unwrap_or(1);
+
+    if file_paths.is_empty() {
+        return Ok(None);
+    }
+
+    file_paths.retain(|path| weight(path) > 0);
+    if file_paths.is_empty() {
+        bail!("every candidate has weight 0 in weights.toml");
+    }
+
+    let mut file_paths = avoiding(file_paths, avoid);
+    let index = WeightedIndex::new(file_paths.iter().map(weight))?;
+    Ok(Some(file_paths.swap_remove(index.sample(rng))))
+}
+
+/// `file_paths` without the ones in `avoid`, or all of them if that leaves nothing.
+fn avoiding(file_paths: Vec<PathBuf>, avoid: &[PathBuf]) -> Vec<PathBuf> {
+    if file_paths.iter().all(|path| avoid.contains(path)) {
+        return file_paths;
+    }
+
+    file_paths
+        .into_iter()
+        .filter(|path| !avoid.contains(path))
+        .collect()
+}
+
+pub fn is_supported_image(path: &Path) -> bool {
+    #[cfg(not(feature = "jxl"))]
+    if has_extension(path, &["jxl"]) {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            warn!(
+                "skipping JPEG XL images like {}, phonk-wl was built without the jxl feature",
+                path.display()
+            );
+        });
+        return false;
+    }
+
+    has_extension(path, IMAGE_EXTENSIONS)
+}
+
+pub fn is_supported_audio(path: &Path) -> bool {
+    has_extension(path, AUDIO_EXTENSIONS)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Lists the regular, non-hidden files in `dir` accepted by `filter`, skipping file names that
+/// match `ignore` or a pattern from the directory's `.phonkignore`. Sorted, so seeded picks don't
+/// depend on the order the filesystem returns them in.
+pub fn list_files(
+    dir: &Path,
+    filter: fn(&Path) -> bool,
+    ignore: &[Pattern],
+) -> Result<Vec<PathBuf>> {
+    let local_ignore = read_ignore_file(&dir.join(".phonkignore"));
+    let mut file_paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let hidden = name.starts_with('.');
+        let ignored = ignore.iter().chain(&local_ignore).any(|p| p.matches(&name));
+
+        if !hidden && !ignored && entry.file_type()?.is_file() && filter(&path) {
+            file_paths.push(path);
+        }
+    }
+
+    file_paths.sort();
+    Ok(file_paths)
+}
+
+/// Whether a show picks the `paused` clip back up rather than stopping it, when it hasn't
+/// `finished` and the show's image doesn't come with a `planned` clip of its own.
+fn resumes(paused: &Path, finished: bool, planned: Option<&[PathBuf]>) -> bool {
+    !finished && planned.is_none_or(|planned| planned.first().map(PathBuf::as_path) == Some(paused))
+}
+
+/// Reads the files listed in a playlist, one path per line, relative ones being relative to the
+/// playlist itself. Blank lines and `#` comments are skipped, and so are missing or unsupported
+/// files, with a warning.
+pub fn read_playlist(playlist: &Path, filter: fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(playlist)
+        .with_context(|| format!("reading {}", playlist.display()))?;
+    let base = playlist.parent().unwrap_or(Path::new(""));
+    let mut file_paths = Vec::new();
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let path = base.join(line);
+        if !path.is_file() {
+            warn!(
+                "skipping {} from {}: not a file",
+                path.display(),
+                playlist.display()
+            );
+        } else if !filter(&path) {
+            warn!(
+                "skipping {} from {}: unsupported format",
+                path.display(),
+                playlist.display()
+            );
+        } else {
+            file_paths.push(path);
+        }
+    }
+
+    Ok(file_paths)
+}
+
+/// Parses a `.phonkignore` file: one glob per line, blank lines and `#` comments are skipped.
+fn read_ignore_file(path: &Path) -> Vec<Pattern> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match Pattern::new(line) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                warn!("ignoring pattern {line:?} in {}: {e}", path.display());
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rng;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("phonk-wl-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fit_scales_large_images_down_only() {
+        let large = Image::new(400, 200);
+        assert_eq!(
+            scale_image(
+                &large,
+                ScaleMode::Fit,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (100, 50)
+        );
+
+        let small = Image::new(40, 20);
+        assert_eq!(
+            scale_image(
+                &small,
+                ScaleMode::Fit,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (40, 20)
+        );
+    }
+
+    #[test]
+    fn size_limits_clamp_small_and_large_images() {
+        let limits = SizeLimits {
+            min: Some(0.5),
+            max: Some(0.8),
+        };
+        let icon = Image::new(20, 10);
+        assert_eq!(
+            scale_image(
+                &icon,
+                ScaleMode::Center,
+                limits,
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (50, 25)
+        );
+
+        let huge = Image::new(1000, 2000);
+        assert_eq!(
+            scale_image(
+                &huge,
+                ScaleMode::Fill,
+                limits,
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (40, 80)
+        );
+
+        let image = Image::new(60, 60);
+        assert_eq!(
+            scale_image(
+                &image,
+                ScaleMode::Center,
+                limits,
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (60, 60)
+        );
+    }
+
+    #[test]
+    fn rotated_images_scale_with_their_new_dimensions() {
+        let mut image = Image::new(400, 100);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+
+        let rotated = transform_image(&image, ImageTransform::Rotate90);
+        assert_eq!(rotated.dimensions(), (100, 400));
+        assert_eq!(rotated.get_pixel(99, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(
+            scale_image(
+                &rotated,
+                ScaleMode::Fit,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                200,
+                200
+            )
+            .dimensions(),
+            (50, 200)
+        );
+
+        let flipped = transform_image(&image, ImageTransform::FlipHorizontal);
+        assert_eq!(flipped.get_pixel(399, 0), &Rgba([255, 0, 0, 255]));
+        assert!(matches!(
+            transform_image(&image, ImageTransform::None),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn fill_and_stretch_cover_the_surface() {
+        let image = Image::new(40, 20);
+        assert_eq!(
+            scale_image(
+                &image,
+                ScaleMode::Fill,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (200, 100)
+        );
+        assert_eq!(
+            scale_image(
+                &image,
+                ScaleMode::Stretch,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (100, 100)
+        );
+        assert_eq!(
+            scale_image(
+                &image,
+                ScaleMode::Center,
+                SizeLimits::default(),
+                FilterType::Triangle,
+                100,
+                100
+            )
+            .dimensions(),
+            (40, 20)
+        );
+    }
+
+    #[test]
+    fn draw_crops_images_larger_than_the_surface() {
+        let mut canvas = vec![0; 10 * 10 * 4];
+        let image = Image::from_pixel(30, 30, Rgba([255, 0, 0, 255]));
+        let (x, y) = centered((10, 10), image.dimensions());
+        draw(
+            &mut canvas,
+            10,
+            10,
+            &[0; 10 * 10 * 4],
+            None,
+            &[(&image, (x, y))],
+            Format::Argb8888,
+        );
+
+        assert!(canvas.chunks_exact(4).all(|px| px == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn draw_places_the_image_at_the_offset() {
+        let mut canvas = vec![0; 4 * 4 * 4];
+        let image = Image::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        draw(
+            &mut canvas,
+            4,
+            4,
+            &[0; 4 * 4 * 4],
+            None,
+            &[(&image, (2, -1))],
+            Format::Argb8888,
+        );
+
+        let red: Vec<usize> = canvas
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, px)| *px == [0, 0, 255, 255])
+            .map(|(i, _)| i)
+            .collect();
+        // only the bottom row of the image lands on the surface, in the top right corner
+        assert_eq!(red, [2, 3]);
+    }
+
+    #[test]
+    fn draw_composites_images_in_order() {
+        let mut canvas = vec![0; 4 * 4 * 4];
+        let red = Image::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        let blue = Image::from_pixel(2, 2, Rgba([0, 0, 255, 255]));
+        draw(
+            &mut canvas,
+            4,
+            4,
+            &[0; 4 * 4 * 4],
+            None,
+            &[(&red, (0, 0)), (&blue, (1, 1)), (&red, (3, 3))],
+            Format::Argb8888,
+        );
+
+        let px = |x: usize, y: usize| &canvas[(y * 4 + x) * 4..][..4];
+        assert_eq!(px(0, 0), [0, 0, 255, 255]);
+        assert_eq!(px(1, 1), [255, 0, 0, 255]);
+        assert_eq!(px(3, 3), [0, 0, 255, 255]);
+        assert_eq!(px(3, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn draw_matches_golden_bgra() {
+        // opaque, fully transparent, half transparent, and half transparent over nothing
+        let image = Image::from_raw(
+            4,
+            1,
+            vec![
+                200, 100, 50, 255, //
+                10, 20, 30, 0, //
+                0, 0, 255, 128, //
+                255, 0, 0, 128,
+            ],
+        )
+        .unwrap();
+        #[rustfmt::skip]
+        let backdrop = [
+            100, 100, 100, 255,
+            100, 100, 100, 255,
+            100, 100, 100, 255,
+            0, 0, 0, 0,
+        ];
+        let mut canvas = vec![0; 4 * 4];
+        draw(
+            &mut canvas,
+            4,
+            1,
+            &backdrop,
+            None,
+            &[(&image, (0, 0))],
+            Format::Argb8888,
+        );
+
+        // Argb8888 is little endian, so every pixel is laid out b, g, r, a
+        #[rustfmt::skip]
+        let golden = [
+            50, 100, 200, 255,
+            100, 100, 100, 255,
+            178, 50, 50, 255,
+            0, 0, 128, 128,
+        ];
+        assert_eq!(canvas, golden);
+    }
+
+    #[test]
+    fn draw_lays_channels_out_for_the_format() {
+        let image = Image::from_pixel(1, 1, Rgba([200, 100, 50, 255]));
+        let drawn = |format| {
+            let backdrop = paint_backdrop(2, 1, Rgba([10, 20, 30, 255]), Backdrop::Solid, format);
+            let mut canvas = vec![0; 2 * 4];
+            draw(
+                &mut canvas,
+                2,
+                1,
+                &backdrop,
+                None,
+                &[(&image, (0, 0))],
+                format,
+            );
+            canvas
+        };
+
+        let bgra = [50, 100, 200, 255, 30, 20, 10, 255];
+        assert_eq!(drawn(Format::Argb8888), bgra);
+        // the alpha byte is still there, the compositor just ignores it
+        assert_eq!(drawn(Format::Xrgb8888), bgra);
+        let translucent = paint_backdrop(
+            1,
+            1,
+            Rgba([10, 20, 30, 128]),
+            Backdrop::Solid,
+            Format::Xrgb8888,
+        );
+        assert_eq!(translucent, [30, 20, 10, 255]);
+        let premultiplied = paint_backdrop(
+            1,
+            1,
+            Rgba([10, 20, 30, 128]),
+            Backdrop::Solid,
+            Format::Argb8888,
+        );
+        assert_eq!(premultiplied, [15, 10, 5, 128]);
+        assert_eq!(
+            drawn(Format::Abgr8888),
+            [200, 100, 50, 255, 10, 20, 30, 255]
+        );
+    }
+
+    #[test]
+    fn snapshots_come_out_as_rgba() {
+        // premultiplied half transparent pixels come out with their full colors
+        let bgra = vec![25, 50, 100, 128];
+
+        assert_eq!(to_rgba(bgra.clone(), Format::Argb8888), [199, 100, 50, 128]);
+        assert_eq!(to_rgba(bgra, Format::Xrgb8888), [100, 50, 25, 255]);
+        assert_eq!(
+            to_rgba(vec![100, 50, 25, 128], Format::Abgr8888),
+            [199, 100, 50, 128]
+        );
+    }
+
+    #[test]
+    fn transforms_turn_the_buffer_counter_clockwise() {
+        // one pixel wide columns, a then b
+        let upright = [1, 1, 1, 1, 2, 2, 2, 2];
+        let turned = |transform| {
+            let mut buffer = [0; 8];
+            transform_pixels(&upright, 2, 1, transform, &mut buffer);
+            [buffer[0], buffer[4]]
+        };
+
+        assert_eq!(turned(Transform::Normal), [1, 2]);
+        // b ends up on top of a
+        assert_eq!(turned(Transform::_90), [2, 1]);
+        assert_eq!(turned(Transform::_180), [2, 1]);
+        assert_eq!(turned(Transform::_270), [1, 2]);
+        assert_eq!(turned(Transform::Flipped), [2, 1]);
+        assert_eq!(turned(Transform::Flipped90), [1, 2]);
+        assert!(swaps_sides(Transform::Flipped270) && !swaps_sides(Transform::_180));
+    }
+
+    #[test]
+    fn damage_follows_the_pixels_through_transforms() {
+        let (width, height) = (4, 3);
+        let region = Region::clipped((1, 0), (2, 2), (width, height)).unwrap();
+        let mut upright = vec![0; (width * height * 4) as usize];
+        for (i, px) in upright.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            if (region.x..region.x + region.width).contains(&x) && y < region.height {
+                px.fill(1);
+            }
+        }
+
+        for transform in [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            let mut buffer = vec![0; upright.len()];
+            transform_pixels(&upright, width, height, transform, &mut buffer);
+            let buffer_width = if swaps_sides(transform) {
+                height
+            } else {
+                width
+            };
+            let damage = region.transformed(width, height, transform);
+
+            for (i, px) in buffer.chunks_exact(4).enumerate() {
+                let (x, y) = (i as u32 % buffer_width, i as u32 / buffer_width);
+                let inside = (damage.x..damage.x + damage.width).contains(&x)
+                    && (damage.y..damage.y + damage.height).contains(&y);
+                assert_eq!(px[0] == 1, inside, "{transform:?} at {x},{y}");
+            }
+        }
+    }
+
+    #[test]
+    fn draw_reports_where_the_images_went() {
+        let image = Image::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        let backdrop = vec![0; 4 * 4 * 4];
+        let mut canvas = backdrop.clone();
+        let drawn = |canvas: &mut [u8], images: &[(&Image, (isize, isize))]| {
+            draw(canvas, 4, 4, &backdrop, None, images, Format::Argb8888)
+        };
+
+        assert_eq!(
+            drawn(&mut canvas, &[(&image, (-1, 1)), (&image, (2, 2))]),
+            Some(Region {
+                x: 0,
+                y: 1,
+                width: 4,
+                height: 3
+            })
+        );
+        assert_eq!(drawn(&mut canvas, &[(&image, (4, 0))]), None);
+        assert_eq!(
+            Region::merge(None, Region::clipped((3, 3), (2, 2), (4, 4))),
+            Some(Region {
+                x: 3,
+                y: 3,
+                width: 1,
+                height: 1
+            })
+        );
+    }
+
+    #[test]
+    fn letterbox_fills_around_the_images() {
+        let mut canvas = vec![0; 4 * 4 * 4];
+        let clear = Image::new(2, 2);
+        let backdrop = paint_backdrop(
+            4,
+            4,
+            Rgba([128, 128, 128, 255]),
+            Backdrop::Solid,
+            Format::Argb8888,
+        );
+        draw(
+            &mut canvas,
+            4,
+            4,
+            &backdrop,
+            Some(Rgba([0, 0, 0, 255])),
+            &[(&clear, (3, 1))],
+            Format::Argb8888,
+        );
+
+        let px = |x: usize, y: usize| &canvas[(y * 4 + x) * 4..][..4];
+        // the transparent image shows the backdrop, only where it's on the canvas
+        assert_eq!(px(3, 1), [128, 128, 128, 255]);
+        assert_eq!(px(3, 2), [128, 128, 128, 255]);
+        assert_eq!(px(2, 1), [0, 0, 0, 255]);
+        assert_eq!(px(3, 3), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn grid_has_a_cell_for_every_image() {
+        assert_eq!(grid_shape(1), (1, 1));
+        assert_eq!(grid_shape(2), (2, 1));
+        assert_eq!(grid_shape(4), (2, 2));
+        assert_eq!(grid_shape(5), (3, 2));
+        assert_eq!(grid_shape(9), (3, 3));
+
+        // grid placement doesn't roll anything
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut at = |i| {
+            place(
+                Placement::Grid,
+                ImageAnchor::Center,
+                i,
+                2,
+                (50, 50),
+                (100, 100),
+                (20, 10),
+                &mut rng,
+            )
+        };
+        assert_eq!(at(0), (15, 20));
+        assert_eq!(at(3), (65, 70));
+    }
+
+    #[test]
+    fn configures_without_a_size_are_skipped() {
+        assert_eq!(configured_size((1920, 1080), (0, 0)), Some((1920, 1080)));
+        assert_eq!(configured_size((0, 0), (480, 270)), Some((480, 270)));
+        assert_eq!(configured_size((0, 300), (480, 270)), Some((480, 300)));
+        assert_eq!(configured_size((0, 0), (0, 0)), None);
+        assert_eq!(configured_size((1920, 0), (0, 0)), None);
+    }
+
+    #[test]
+    fn anchors_pin_images_to_their_cell() {
+        let cell = (100, 50);
+        assert_eq!(ImageAnchor::Center.offset(cell, (20, 10)), (40, 20));
+        assert_eq!(ImageAnchor::TopLeft.offset(cell, (20, 10)), (0, 0));
+        assert_eq!(ImageAnchor::Right.offset(cell, (20, 10)), (80, 20));
+        assert_eq!(ImageAnchor::BottomRight.offset(cell, (20, 10)), (80, 40));
+        // too big, cropped away from the anchor
+        assert_eq!(ImageAnchor::Bottom.offset(cell, (120, 60)), (-10, -10));
+
+        let dir = temp_dir("anchor");
+        let image = dir.join("skull.png");
+        assert_eq!(read_anchor(&image), ImageAnchor::Center);
+        std::fs::write(dir.join("skull.png.anchor"), "top-right\n").unwrap();
+        assert_eq!(read_anchor(&image), ImageAnchor::TopRight);
+        std::fs::write(dir.join("skull.png.anchor"), "upstairs").unwrap();
+        assert_eq!(read_anchor(&image), ImageAnchor::Center);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn random_placement_keeps_half_the_image_on_screen() {
+        let mut rng = StdRng::seed_from_u64(4);
+
+        for _ in 0..100 {
+            let (x, y) = place(
+                Placement::Random,
+                ImageAnchor::Center,
+                0,
+                1,
+                (100, 50),
+                (100, 50),
+                (40, 20),
+                &mut rng,
+            );
+            assert!((-20..=80).contains(&x), "{x}");
+            assert!((-10..=40).contains(&y), "{y}");
+        }
+    }
+
+    #[test]
+    fn shows_past_the_rate_are_throttled() {
+        let now = Instant::now() + Duration::from_secs(120);
+        let mut recent = VecDeque::from([
+            now - Duration::from_secs(90),
+            now - Duration::from_secs(30),
+            now - Duration::from_secs(1),
+        ]);
+
+        assert!(over_rate(&mut recent, now, 2));
+        assert_eq!(recent.len(), 2);
+        assert!(!over_rate(&mut recent, now, 3));
+        assert!(!over_rate(&mut recent, now + Duration::from_secs(45), 2));
+    }
+
+    #[test]
+    fn jitter_stays_within_range_and_above_the_floor() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let base = Duration::from_secs(5);
+
+        assert_eq!(jittered(base, Duration::ZERO, &mut rng), base);
+        for _ in 0..100 {
+            let interval = jittered(base, Duration::from_secs(2), &mut rng);
+            assert!(
+                (3.0..=7.0).contains(&interval.as_secs_f64()),
+                "{interval:?}"
+            );
+
+            let interval = jittered(Duration::from_secs(1), Duration::from_secs(10), &mut rng);
+            assert!(interval >= MIN_INTERVAL, "{interval:?}");
+        }
+    }
+
+    #[test]
+    fn bounce_reflects_off_the_edges() {
+        let mut bounce = Bounce {
+            bounds: (100.0, 10.0),
+            position: (90.0, 5.0),
+            velocity: (20.0, -10.0),
+            last: Instant::now(),
+        };
+
+        // x runs into the edge at 100 and y into the one at 0, both come back the way they went
+        assert_eq!(bounce.step(1.0), (90, 5));
+        assert_eq!(bounce.velocity, (-20.0, 10.0));
+
+        // a full round trip on y brings it back to where it started
+        bounce.step(2.0);
+        assert_eq!(bounce.position, (50.0, 5.0));
+    }
+
+    #[test]
+    fn bounce_centers_images_that_dont_fit() {
+        let mut bounce = Bounce::new(
+            (100, 100),
+            (120, 50),
+            (0, 0),
+            200.0,
+            &mut StdRng::seed_from_u64(1),
+        );
+
+        for _ in 0..10 {
+            let (x, _) = bounce.step(0.1);
+            assert_eq!(x, -10);
+        }
+    }
+
+    #[test]
+    fn animation_frames_follow_their_delays() {
+        let delays = [100, 50, 200].map(Duration::from_millis);
+        let at = |ms| frame_at(&delays, Duration::from_millis(ms));
+
+        assert_eq!(at(0), (0, Some(Duration::from_millis(100))));
+        assert_eq!(at(120), (1, Some(Duration::from_millis(30))));
+        assert_eq!(at(349), (2, Some(Duration::from_millis(1))));
+        // loops back around
+        assert_eq!(at(360), (0, Some(Duration::from_millis(90))));
+
+        assert_eq!(
+            frame_at(&[Duration::ZERO], Duration::from_secs(3)),
+            (0, None)
+        );
+    }
+
+    #[test]
+    fn animated_gifs_keep_every_frame() {
+        let dir = temp_dir("gif");
+        let path = dir.join("spin.gif");
+
+        let frames = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])].map(|color| {
+            image::Frame::from_parts(
+                Image::from_pixel(4, 4, color),
+                0,
+                0,
+                image::Delay::from_numer_denom_ms(70, 1),
+            )
+        });
+        let mut encoder = image::codecs::gif::GifEncoder::new(File::create(&path).unwrap());
+        encoder.encode_frames(frames).unwrap();
+        drop(encoder);
+
+        let picture = load_image(&path, u64::MAX).unwrap();
+        assert_eq!(picture.frames.len(), 2);
+        assert_eq!(picture.delays, [Duration::from_millis(70); 2]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn crossfade_mixes_linearly() {
+        let mut canvas = vec![200, 0, 100, 255];
+        crossfade(&mut canvas, &[0, 200, 100, 255], 0.25);
+
+        assert_eq!(canvas, [50, 150, 100, 255]);
+    }
+
+    #[test]
+    fn buffer_layout_scales_the_logical_size() {
+        assert_eq!(
+            BufferLayout::new(1920, 1080, 2).unwrap(),
+            BufferLayout {
+                width: 3840,
+                height: 2160,
+                stride: 3840 * 4,
+                size: 3840 * 4 * 2160,
+            }
+        );
+    }
+
+    #[test]
+    fn huge_buffers_are_rejected() {
+        assert!(BufferLayout::new(u32::MAX, 1, 1).is_err());
+        assert!(BufferLayout::new(1 << 20, 1 << 20, 1).is_err());
+        assert!(BufferLayout::new(70000, 70000, 70000).is_err());
+        assert!(BufferLayout::new(100, 100, i32::MAX).is_err());
+        assert!(BufferLayout::new(100, 100, -1).is_err());
+    }
+
+    #[test]
+    fn normalization_targets_rms_without_clipping() {
+        // a quiet square wave gets boosted to the target level
+        let quiet = [0.05, -0.05].repeat(100);
+        assert!((normalization_gain(&quiet) - 2.0).abs() < 1e-4);
+
+        // a single loud peak holds the gain back so the peak lands at full scale
+        let mut spiky = vec![0.01; 1000];
+        spiky[0] = 0.5;
+        assert!((normalization_gain(&spiky) - 2.0).abs() < 1e-4);
+
+        assert_eq!(normalization_gain(&[0.0; 10]), 1.0);
+        assert_eq!(normalization_gain(&[]), 1.0);
+    }
+
+    #[test]
+    fn gradient_runs_from_background_to_bottom() {
+        let backdrop = Backdrop::VerticalGradient {
+            bottom: Rgba([0, 0, 0, 255]),
+        };
+        let pixels = paint_backdrop(1, 4, Rgba([200, 100, 40, 255]), backdrop, Format::Argb8888);
+        let rows: Vec<&[u8]> = pixels.chunks_exact(4).collect();
+
+        // BGRA, so red is the third byte
+        assert!(rows.windows(2).all(|pair| pair[0][2] > pair[1][2]));
+        assert_eq!(rows[0][3], 255);
+    }
+
+    #[test]
+    fn vignette_darkens_the_edges() {
+        let pixels = paint_backdrop(
+            9,
+            9,
+            Rgba([200, 200, 200, 255]),
+            Backdrop::Vignette { strength: 0.8 },
+            Format::Argb8888,
+        );
+        let at = |x: usize, y: usize| pixels[(y * 9 + x) * 4];
+
+        assert!(at(4, 4) > at(0, 0));
+        assert!(at(4, 4) > at(8, 4));
+        assert!(at(0, 4) > at(0, 0));
+    }
+
+    #[test]
+    fn tint_and_opacity_apply_to_the_image() {
+        let mut image = Image::from_pixel(1, 1, Rgba([0, 100, 200, 200]));
+        style_image(&mut image, 0.5, Some(Rgba([255, 0, 0, 128])));
+
+        let px = image.get_pixel(0, 0);
+        assert!(px[0] > 120 && px[1] < 60 && px[2] < 110, "{px:?}");
+        assert_eq!(px[3], 100);
+
+        let mut untouched = Image::from_pixel(1, 1, Rgba([1, 2, 3, 4]));
+        style_image(&mut untouched, 1.0, None);
+        assert_eq!(untouched.get_pixel(0, 0), &Rgba([1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn opacity_scales_every_channel() {
+        let mut canvas = vec![200, 100, 50, 255];
+
+        apply_opacity(&mut canvas, 0.5);
+        assert_eq!(canvas, [100, 50, 25, 127]);
+
+        apply_opacity(&mut canvas, 0.0);
+        assert_eq!(canvas, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn visual_fade_progress_is_clamped() {
+        let fade = VisualFade {
+            start: Instant::now() - Duration::from_secs(1),
+            showing: true,
+        };
+
+        assert_eq!(fade.progress(Duration::from_millis(150)), 1.0);
+        assert_eq!(fade.progress(Duration::ZERO), 1.0);
+    }
+
+    #[test]
+    fn weights_skew_the_odds() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let pool: Vec<PathBuf> = ["a.png", "b.png", "c.png"].map(PathBuf::from).into();
+        let weights = HashMap::from([(PathBuf::from("a.png"), 8), (PathBuf::from("c.png"), 0)]);
+
+        let picks: Vec<PathBuf> = (0..1000)
+            .map(|_| {
+                pick_weighted(pool.clone(), &[], &weights, &mut rng)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        let count = |name: &str| picks.iter().filter(|p| *p == Path::new(name)).count();
+
+        assert_eq!(count("c.png"), 0);
+        assert!(count("a.png") > count("b.png") * 4);
+    }
+
+    #[test]
+    fn all_zero_weights_are_an_error() {
+        let pool: Vec<PathBuf> = ["a.png"].map(PathBuf::from).into();
+        let weights = HashMap::from([(PathBuf::from("a.png"), 0)]);
+
+        assert!(pick_weighted(pool, &[], &weights, &mut rng()).is_err());
+        assert!(
+            pick_weighted(Vec::new(), &[], &weights, &mut rng())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn consecutive_picks_differ() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let pool: Vec<PathBuf> = ["a.png", "b.png", "c.png"].map(PathBuf::from).into();
+
+        let mut last = pick_file(pool.clone(), &[], &mut rng).unwrap();
+        for _ in 0..100 {
+            let next = pick_file(pool.clone(), &[last.clone()], &mut rng).unwrap();
+            assert_ne!(next, last);
+            last = next;
+        }
+    }
+
+    #[test]
+    fn collages_avoid_every_image_on_screen() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let pool: Vec<PathBuf> = ["a.png", "b.png", "c.png", "d.png"]
+            .map(PathBuf::from)
+            .into();
+
+        for _ in 0..20 {
+            let picked = pick_file(pool.clone(), &pool[..3], &mut rng);
+            assert_eq!(picked, Some(PathBuf::from("d.png")));
+        }
+    }
+
+    #[test]
+    fn single_file_pool_repeats() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let pool = vec![PathBuf::from("a.png")];
+
+        let picked = pick_file(pool.clone(), &pool, &mut rng);
+        assert_eq!(picked, Some(PathBuf::from("a.png")));
+    }
+
+    #[test]
+    fn ignored_files_are_never_selectable() {
+        let dir = temp_dir("ignore");
+        for name in ["keep.png", "wip-1.png", "wip-2.png", "secret.png"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        std::fs::write(dir.join(".phonkignore"), "# not ready yet\n\nsecret.png\n").unwrap();
+        let ignore = [Pattern::new("wip-*").unwrap()];
+
+        let images = list_files(&dir, is_supported_image, &ignore).unwrap();
+        assert_eq!(images, vec![dir.join("keep.png")]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gain_is_read_from_sidecar() {
+        let dir = temp_dir("gain");
+        std::fs::write(dir.join("loud.ogg.gain"), "0.25\n").unwrap();
+        std::fs::write(dir.join("broken.ogg.gain"), "loud").unwrap();
+        std::fs::write(dir.join("boosted.ogg.gain"), "3").unwrap();
+
+        assert_eq!(read_gain(&dir.join("loud.ogg")), 0.25);
+        assert_eq!(read_gain(&dir.join("broken.ogg")), 1.0);
+        assert_eq!(read_gain(&dir.join("boosted.ogg")), 1.0);
+        assert_eq!(read_gain(&dir.join("quiet.ogg")), 1.0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_png_fails_to_load() {
+        let dir = temp_dir("truncated");
+        let path = dir.join("broken.png");
+        let mut png = Vec::new();
+        Image::from_pixel(16, 16, Rgba([255, 0, 0, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        std::fs::write(&path, &png[..png.len() / 2]).unwrap();
+
+        assert!(load_image(&path, u64::MAX).is_err());
+
+        std::fs::write(&path, &png).unwrap();
+        assert_eq!(
+            load_image(&path, u64::MAX).unwrap().frames[0].dimensions(),
+            (16, 16)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_images_are_skipped() {
+        let dir = temp_dir("oversized");
+        let path = dir.join("big.png");
+        Image::from_pixel(16, 16, Rgba([255, 0, 0, 255]))
+            .save(&path)
+            .unwrap();
+
+        let e = load_image(&path, 255).err().unwrap();
+        assert!(format!("{e:#}").contains("16x16"), "{e:#}");
+        assert!(load_image(&path, 256).is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Writes a 16x16 sample in `format` and checks that it decodes back at that size.
+    fn decodes_sample(format: image::ImageFormat, extension: &str) {
+        let dir = temp_dir(extension);
+        let path = dir.join(format!("sample.{extension}"));
+        assert!(is_supported_image(&path));
+
+        Image::from_pixel(16, 16, Rgba([255, 0, 0, 255]))
+            .save_with_format(&path, format)
+            .unwrap();
+        assert_eq!(
+            load_image(&path, u64::MAX).unwrap().frames[0].dimensions(),
+            (16, 16)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn decodes_webp() {
+        decodes_sample(image::ImageFormat::WebP, "webp");
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn decodes_avif() {
+        decodes_sample(image::ImageFormat::Avif, "avif");
+    }
+
+    #[cfg(not(feature = "jxl"))]
+    #[test]
+    fn jxl_needs_the_feature() {
+        let path = Path::new("sample.jxl");
+
+        assert!(!is_supported_image(path));
+        let e = load_image(path, u64::MAX).err().unwrap();
+        assert!(format!("{e:#}").contains("--features jxl"), "{e:#}");
+    }
+
+    #[test]
+    fn empty_directory_is_an_error() {
+        let dir = temp_dir("empty");
+
+        assert!(
+            MediaDir::images(dir.clone())
+                .pick(&[], &mut Picker::Random, &[], &mut rng())
+                .is_err()
+        );
+        assert!(
+            MediaDir::audio(dir.clone())
+                .pick(&[], &mut Picker::Random, &[], &mut rng())
+                .is_err()
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_directory_is_an_error() {
+        let dir = temp_dir("missing");
+        let missing = dir.join("nope");
+
+        assert!(
+            MediaDir::images(missing.clone())
+                .pick(&[], &mut Picker::Random, &[], &mut rng())
+                .is_err()
+        );
+        assert!(
+            MediaDir::audio(missing.clone())
+                .pick(&[], &mut Picker::Random, &[], &mut rng())
+                .is_err()
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn paused_audio_resumes_unless_the_image_brings_another_clip() {
+        let paused = Path::new("phonk.ogg");
+        let same = [PathBuf::from("phonk.ogg")];
+        let other = [PathBuf::from("drift.ogg")];
+
+        assert!(resumes(paused, false, None));
+        assert!(resumes(paused, false, Some(&same)));
+        assert!(!resumes(paused, false, Some(&other)));
+        assert!(!resumes(paused, true, None));
+    }
+
+    #[test]
+    fn playlists_skip_missing_and_unsupported_files() {
+        let dir = temp_dir("playlist");
+        let other = temp_dir("playlist-other");
+        std::fs::write(dir.join("a.png"), b"").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+        std::fs::write(other.join("b.png"), b"").unwrap();
+        let playlist = dir.join("list.txt");
+        std::fs::write(
+            &playlist,
+            format!(
+                "# favourites\na.png\n\nmissing.png\nnotes.txt\n{}\n",
+                other.join("b.png").display()
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_playlist(&playlist, is_supported_image).unwrap(),
+            [dir.join("a.png"), other.join("b.png")]
+        );
+
+        let mut images = MediaDir::images(playlist.clone()).read_as_playlist();
+        let picked = images
+            .pick(&[], &mut Picker::Random, &[], &mut rng())
+            .unwrap();
+        assert!(picked == dir.join("a.png") || picked == other.join("b.png"));
+
+        // kept until a reload, a change in a watched directory doesn't read it again
+        std::fs::write(&playlist, "a.png\n").unwrap();
+        images.invalidate();
+        assert_eq!(images.files.as_deref().map(<[_]>::len), Some(2));
+        images.reload();
+        assert_eq!(images.files, Some(vec![dir.join("a.png")]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(other).unwrap();
+    }
+
+    #[test]
+    fn watched_directories_keep_their_listing_until_invalidated() {
+        let dir = temp_dir("watched");
+        std::fs::write(dir.join("a.png"), b"").unwrap();
+        let mut images = MediaDir::images(dir.clone());
+        images.watched = true;
+
+        let pick = |images: &mut MediaDir| {
+            images
+                .pick(&[], &mut Picker::Random, &[], &mut rng())
+                .unwrap()
+        };
+        assert_eq!(pick(&mut images), dir.join("a.png"));
+
+        std::fs::remove_file(dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("b.png"), b"").unwrap();
+        assert_eq!(pick(&mut images), dir.join("a.png"));
+
+        images.invalidate();
+        assert_eq!(pick(&mut images), dir.join("b.png"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn same_seed_picks_the_same_sequence() {
+        let dir = temp_dir("seeded");
+        for name in ["a.png", "b.png", "c.png", "d.png", "e.png"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let session = |seed| {
+            let mut images = MediaDir::images(dir.clone());
+            let mut picker = Picker::Shuffle(Playlist::default());
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..12)
+                .map(|_| images.pick(&[], &mut picker, &[], &mut rng).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(session(7), session(7));
+        assert_ne!(session(7), session(8));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_supported_files_are_selectable() {
+        let dir = temp_dir("mixed");
+        for name in [
+            "a.png",
+            "b.JPG",
+            "c.ogg",
+            "notes.txt",
+            ".hidden.png",
+            "noext",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        std::fs::create_dir(dir.join("sub.png")).unwrap();
+
+        let mut images = list_files(&dir, is_supported_image, &[]).unwrap();
+        images.sort();
+        assert_eq!(images, vec![dir.join("a.png"), dir.join("b.JPG")]);
+        assert_eq!(
+            list_files(&dir, is_supported_audio, &[]).unwrap(),
+            vec![dir.join("c.ogg")]
+        );
+
+        for _ in 0..20 {
+            assert!(is_supported_image(
+                &MediaDir::images(dir.clone())
+                    .pick(&[], &mut Picker::Random, &[], &mut rng())
+                    .unwrap()
+            ));
+            assert_eq!(
+                MediaDir::audio(dir.clone())
+                    .pick(&[], &mut Picker::Random, &[], &mut rng())
+                    .unwrap(),
+                dir.join("c.ogg")
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,128 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use image::{ImageBuffer, Rgba};
+
+pub type Image = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+/// A decoded image file. Still images have a single frame, animated ones every frame along with
+/// how long it stays up.
+pub struct Picture {
+    pub frames: Vec<Image>,
+    pub delays: Vec<Duration>,
+}
+
+impl Picture {
+    pub fn still(image: Image) -> Self {
+        Self {
+            frames: vec![image],
+            delays: vec![Duration::ZERO],
+        }
+    }
+}
+
+/// Keeps the most recently shown decoded images around so repeat shows skip the decode.
+pub struct ImageCache {
+    capacity: usize,
+    images: HashMap<PathBuf, Arc<Picture>>,
+    /// Least recently used first.
+    recent: VecDeque<PathBuf>,
+}
+
+impl ImageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            images: HashMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn get_or_load(
+        &mut self,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Result<Picture>,
+    ) -> Result<Arc<Picture>> {
+        if let Some(image) = self.images.get(path) {
+            let image = image.clone();
+            self.touch(path);
+            return Ok(image);
+        }
+
+        let image = Arc::new(load(path)?);
+
+        if self.capacity == 0 {
+            return Ok(image);
+        }
+
+        while self.images.len() >= self.capacity {
+            let Some(oldest) = self.recent.pop_front() else {
+                break;
+            };
+            self.images.remove(&oldest);
+        }
+
+        self.images.insert(path.to_path_buf(), image.clone());
+        self.recent.push_back(path.to_path_buf());
+
+        Ok(image)
+    }
+
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.recent.clear();
+    }
+
+    fn touch(&mut self, path: &Path) {
+        if let Some(i) = self.recent.iter().position(|p| p == path) {
+            let path = self.recent.remove(i).unwrap();
+            self.recent.push_back(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let loads = Cell::new(0);
+        let load = |_: &Path| {
+            loads.set(loads.get() + 1);
+            Ok(Picture::still(Image::new(1, 1)))
+        };
+        let mut cache = ImageCache::new(2);
+
+        cache.get_or_load(Path::new("a"), load).unwrap();
+        cache.get_or_load(Path::new("b"), load).unwrap();
+        cache.get_or_load(Path::new("a"), load).unwrap();
+        assert_eq!(loads.get(), 2);
+
+        // "b" is the least recently used, so it makes room for "c"
+        cache.get_or_load(Path::new("c"), load).unwrap();
+        cache.get_or_load(Path::new("a"), load).unwrap();
+        assert_eq!(loads.get(), 3);
+
+        cache.get_or_load(Path::new("b"), load).unwrap();
+        assert_eq!(loads.get(), 4);
+    }
+
+    #[test]
+    fn zero_capacity_never_caches() {
+        let mut cache = ImageCache::new(0);
+
+        cache
+            .get_or_load(Path::new("a"), |_| Ok(Picture::still(Image::new(1, 1))))
+            .unwrap();
+
+        assert!(cache.images.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use fontdue::{Font, FontSettings};
+use image::Rgba;
+
+use crate::cache::Image;
+
+/// Gap between an image and its caption, in buffer pixels at scale 1.
+const CAPTION_GAP: f32 = 4.0;
+
+/// Rasterizes captions with the configured font, size and color.
+pub struct Captioner {
+    font: Font,
+    size: f32,
+    color: Rgba<u8>,
+}
+
+impl Captioner {
+    pub fn load(path: &Path, size: f32, color: Rgba<u8>) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let font = Font::from_bytes(data, FontSettings::default())
+            .map_err(|e| anyhow!("parsing {}: {e}", path.display()))?;
+
+        Ok(Self { font, size, color })
+    }
+
+    /// Draws `text` on a single line at `scale`, cut off at `max_width`. `None` if there's
+    /// nothing to draw.
+    pub fn render(&self, text: &str, scale: f32, max_width: u32) -> Option<Image> {
+        let px = self.size * scale;
+        let line = self.font.horizontal_line_metrics(px)?;
+        let height = (line.ascent - line.descent).ceil() as u32;
+        let glyphs: Vec<_> = text.chars().map(|c| self.font.rasterize(c, px)).collect();
+        let width = (glyphs
+            .iter()
+            .map(|(metrics, _)| metrics.advance_width)
+            .sum::<f32>()
+            .ceil() as u32)
+            .min(max_width);
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let Rgba([r, g, b, a]) = self.color;
+        let mut image = Image::new(width, height);
+        let mut pen = 0.0;
+
+        for (metrics, coverage) in glyphs {
+            let left = pen as i32 + metrics.xmin;
+            let top = (line.ascent - (metrics.ymin + metrics.height as i32) as f32) as i32;
+
+            for (i, alpha) in coverage.iter().enumerate() {
+                let x = left + (i % metrics.width.max(1)) as i32;
+                let y = top + (i / metrics.width.max(1)) as i32;
+
+                if *alpha > 0 && x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                    let alpha = (u16::from(*alpha) * u16::from(a) / 255) as u8;
+                    let px = image.get_pixel_mut(x as u32, y as u32);
+                    if alpha > px[3] {
+                        *px = Rgba([r, g, b, alpha]);
+                    }
+                }
+            }
+
+            pen += metrics.advance_width;
+        }
+
+        Some(image)
+    }
+}
+
+/// The caption for an image: the first line of a `<image>.txt` sidecar next to it, or its file
+/// name without the extension.
+pub fn caption_for(image_path: &Path) -> String {
+    let mut sidecar = image_path.as_os_str().to_owned();
+    sidecar.push(".txt");
+
+    if let Ok(contents) = std::fs::read_to_string(PathBuf::from(sidecar))
+        && let Some(line) = contents.lines().next()
+    {
+        return line.trim().to_string();
+    }
+
+    image_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Where a `caption` goes for an image at `position` of `size`: centered under it, moved up and
+/// across as needed to stay on a `surface` sized canvas.
+pub fn caption_position(
+    surface: (u32, u32),
+    position: (isize, isize),
+    size: (u32, u32),
+    caption: (u32, u32),
+    scale: f32,
+) -> (isize, isize) {
+    let gap = (CAPTION_GAP * scale) as isize;
+    let x = position.0 + (size.0 as isize - caption.0 as isize) / 2;
+    let y = position.1 + size.1 as isize + gap;
+
+    (
+        x.clamp(0, (surface.0 as isize - caption.0 as isize).max(0)),
+        y.clamp(0, (surface.1 as isize - caption.1 as isize).max(0)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captions_come_from_sidecars_or_file_names() {
+        let dir = std::env::temp_dir().join(format!("phonk-wl-caption-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("skull.png.txt"), "  Freaky skull \nsecond line").unwrap();
+
+        assert_eq!(caption_for(&dir.join("skull.png")), "Freaky skull");
+        assert_eq!(caption_for(&dir.join("troll.face.png")), "troll.face");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn captions_stay_on_the_surface() {
+        // centered under a small image
+        assert_eq!(
+            caption_position((100, 100), (40, 10), (20, 20), (10, 8), 1.0),
+            (45, 34)
+        );
+        // an image filling the surface gets its caption over its bottom edge
+        assert_eq!(
+            caption_position((100, 100), (0, 0), (100, 100), (30, 8), 1.0),
+            (35, 92)
+        );
+        // a caption wider than its image near the edge is pulled back in
+        assert_eq!(
+            caption_position((100, 100), (-5, 0), (20, 20), (40, 8), 2.0),
+            (0, 28)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    app::{
+        is_supported_audio, is_supported_image, list_files, load_audio, load_image, read_playlist,
+    },
+    config::Config,
+    pack,
+};
+
+/// Tries every file the overlay could pick, printing one line per file and failing if any of
+/// them can't be decoded.
+pub fn run(config: &Config) -> Result<()> {
+    let ignore = config.ignore_patterns()?;
+    let (mut images, mut audio) = (Vec::new(), Vec::new());
+    match &config.packs_dir {
+        Some(packs_dir) => {
+            for pack in pack::all(packs_dir, &ignore)? {
+                images.extend(pack.images);
+                audio.extend(pack.audio);
+            }
+        }
+        None => {
+            images = match &config.image_playlist {
+                Some(playlist) => read_playlist(playlist, is_supported_image)?,
+                None => list_files(&config.image_dir, is_supported_image, &ignore)?,
+            };
+            audio = match &config.music_playlist {
+                Some(playlist) => read_playlist(playlist, is_supported_audio)?,
+                None => list_files(&config.music_dir, is_supported_audio, &ignore)?,
+            };
+        }
+    }
+    let total = images.len() + audio.len();
+
+    let failed = check_all(&images, |path| {
+        load_image(path, config.max_image_pixels).map(drop)
+    }) + check_all(&audio, |path| load_audio(path).map(drop));
+
+    println!("{} of {total} files ok", total - failed);
+
+    if failed > 0 {
+        bail!("{failed} of {total} files can't be decoded");
+    }
+
+    Ok(())
+}
+
+/// Prints the result of `check` for each of `paths`, returning how many failed.
+fn check_all(paths: &[PathBuf], check: impl Fn(&Path) -> Result<()>) -> usize {
+    let mut failed = 0;
+
+    for path in paths {
+        match check(path) {
+            Ok(()) => println!("ok\t{}", path.display()),
+            Err(e) => {
+                println!("FAIL\t{}: {e:#}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_files_that_fail_to_decode() {
+        let dir = std::env::temp_dir().join(format!("phonk-wl-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let broken = vec![dir.join("broken.png")];
+        std::fs::write(&broken[0], b"not a png").unwrap();
+
+        assert_eq!(
+            check_all(&broken, |path| load_image(path, u64::MAX).map(drop)),
+            1
+        );
+        assert_eq!(check_all(&broken, |_| Ok(())), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
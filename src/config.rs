@@ -0,0 +1,1050 @@
+use std::{collections::HashMap, env, fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use image::Rgba;
+use serde::{Deserialize, Deserializer, de::Error as _};
+
+use crate::schedule::{ActiveWindow, DatedShow};
+
+/// Most images a collage can have, an 8x8 grid.
+const MAX_IMAGES_PER_SHOW: usize = 64;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Name of the entry in `themes` to use, the top level settings alone without one.
+    pub theme: Option<String>,
+    /// Named sets of settings, each overriding the top level ones it lists.
+    pub themes: HashMap<String, Theme>,
+    pub image_dir: PathBuf,
+    pub music_dir: PathBuf,
+    /// File listing the images to pick from, one path per line. Replaces `image_dir` when set.
+    pub image_playlist: Option<PathBuf>,
+    /// Like `image_playlist`, for audio. Replaces `music_dir` when set.
+    pub music_playlist: Option<PathBuf>,
+    /// Directory of packs, subdirectories with their own images and audio. Replaces `image_dir`
+    /// and `music_dir` when set.
+    pub packs_dir: Option<PathBuf>,
+    /// Name of the pack to always use instead of a random one.
+    pub pack: Option<String>,
+    /// URL of a JSON manifest listing images and audio to download. Replaces `image_dir` and
+    /// `music_dir` with a download cache when set.
+    pub source: Option<String>,
+    /// Wait before the first scheduled show, independent of `interval_secs`.
+    pub startup_delay_secs: u64,
+    pub interval_secs: u64,
+    /// How far each wait between overlays may randomly stray from `interval_secs`.
+    pub jitter_secs: f64,
+    /// Shortest wait after any show, manual ones included, before the next scheduled one.
+    pub cooldown_secs: u64,
+    /// Most shows, manual ones included, in any minute before scheduled ones are skipped.
+    pub max_shows_per_minute: Option<usize>,
+    pub duration_secs: u64,
+    /// Show the first overlay as soon as phonk-wl starts instead of after the first wait.
+    pub show_on_start: bool,
+    /// Odds of each scheduled show actually happening, from 0.0 to 1.0.
+    pub trigger_chance: f64,
+    /// Seeds the random choices so sessions are reproducible, they're unpredictable without it.
+    pub seed: Option<u64>,
+    /// Local times of day shows are allowed in, empty means always.
+    pub active_hours: Vec<ActiveWindow>,
+    /// Local times of day the audio is silenced in, overlays still show.
+    pub mute_hours: Vec<ActiveWindow>,
+    /// Images and clips to show on specific dates instead of random picks.
+    pub schedule: Vec<DatedShow>,
+    pub duration: DurationMode,
+    /// Longest an overlay following its audio stays up.
+    pub max_duration_secs: u64,
+    pub scale: ScaleMode,
+    /// How images are resampled when they're scaled, from fast to pretty.
+    pub scale_filter: ScaleFilter,
+    /// Smallest and largest the image is drawn, as fractions of the largest size that fits the
+    /// overlay. Both unset leaves the size to `scale`.
+    pub min_image_size: Option<f32>,
+    pub max_image_size: Option<f32>,
+    /// Tint drawn behind the image, as `#rrggbb` or `#rrggbbaa`.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub background: Rgba<u8>,
+    pub backdrop: Backdrop,
+    /// Fill for the parts of the overlay the image doesn't cover, the backdrop then only shows
+    /// through the image's transparent areas.
+    #[serde(deserialize_with = "deserialize_optional_color")]
+    pub letterbox_color: Option<Rgba<u8>>,
+    /// Draw a caption under each image, from a `<image>.txt` sidecar or the file name.
+    pub show_caption: bool,
+    /// TrueType or OpenType font the captions are drawn in.
+    pub caption_font: Option<PathBuf>,
+    /// Caption font size in logical pixels.
+    pub caption_size: f32,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub caption_color: Rgba<u8>,
+    /// Opacity of the image itself from 0.0 to 1.0, on top of any fade.
+    pub image_opacity: f32,
+    /// Color washed over the image, its alpha is how strongly.
+    #[serde(deserialize_with = "deserialize_optional_color")]
+    pub image_tint: Option<Rgba<u8>>,
+    /// Each show rotates or mirrors the image with one of these, picked at random.
+    pub transforms: Vec<ImageTransform>,
+    pub animation: Animation,
+    pub transition: Transition,
+    pub transition_ms: u64,
+    /// How fast a bouncing image moves, in logical pixels per second.
+    pub bounce_speed: f32,
+    /// Playback volume from 0.0 to 1.0.
+    pub volume: f32,
+    /// Even out the loudness of the clips, on top of any `.gain` sidecars.
+    pub normalize: bool,
+    /// Repeat the clip for as long as the overlay is up.
+    pub loop_audio: bool,
+    pub fade_in_ms: u64,
+    pub fade_out_ms: u64,
+    pub audio_overlap: AudioOverlapPolicy,
+    /// What hiding the overlay does to the clip that's playing.
+    pub hide_audio_behavior: HideAudioBehavior,
+    /// Most clips playing at once with the `mix` overlap policy, the oldest are stopped first.
+    pub max_concurrent_sounds: usize,
+    /// Never open an audio device, overlays are shown silently.
+    pub no_audio: bool,
+    /// Odds of a show playing no audio, rolled again for every show.
+    pub silent_chance: f64,
+    /// How long the overlay takes to fade in and out, 0 shows and hides it instantly.
+    pub fade_ms: u64,
+    /// The curve the overlay and audio fades follow.
+    pub fade_easing: Easing,
+    /// Globs matched against file names in the media directories, matching files are never shown.
+    pub ignore: Vec<String>,
+    /// Show every image once before repeating any, like the audio does.
+    pub shuffle_images: bool,
+    /// Pick a separate image for every output instead of showing the same one on all of them.
+    pub per_output_images: bool,
+    /// How many images to show at once, more than one makes a collage.
+    pub images_per_show: usize,
+    /// How the images of a collage are laid out.
+    pub placement: Placement,
+    /// How many images flash by at the start of a show, the last one stays up. 1 is no burst.
+    pub burst_count: usize,
+    /// How long each image of a burst is up.
+    pub burst_frame_ms: u64,
+    /// How many decoded images to keep in memory, 0 disables the cache.
+    pub image_cache_size: usize,
+    /// Images with more pixels than this are skipped without being decoded.
+    pub max_image_pixels: u64,
+    /// Names or descriptions of the outputs to show the overlay on, empty means all of them.
+    pub outputs: Vec<String>,
+    /// Output names or descriptions to the stereo position of the audio for shows on them, from
+    /// -1.0 (left) to 1.0 (right). Unlisted outputs play centered.
+    pub output_pan: HashMap<String, f32>,
+    pub position: Position,
+    /// Gap between the overlay and the screen edges it's anchored to, in logical pixels.
+    pub margin: i32,
+    /// Overlay size in logical pixels, ignored when fullscreen.
+    pub width: u32,
+    pub height: u32,
+    pub layer: OverlayLayer,
+    /// Space reserved for the overlay at the edge it's anchored to, -1 draws over panels and
+    /// 0 or more makes room for it.
+    pub exclusive_zone: i32,
+    /// Let the overlay take keyboard focus, any key press then hides it.
+    pub dismiss_on_key: bool,
+    /// Hide the overlay when it's clicked.
+    pub dismiss_on_click: bool,
+    /// Which seat's keys and clicks dismiss the overlay.
+    pub dismiss_seat: DismissSeat,
+    /// Where the `snapshot` command saves the overlay, `{output}` is replaced by the output name.
+    pub snapshot_path: PathBuf,
+    /// Shell command run on every show, with the image and audio in `PHONK_IMAGE` and
+    /// `PHONK_AUDIO`.
+    pub on_show_cmd: Option<String>,
+    /// Shell command run whenever the overlay hides.
+    pub on_hide_cmd: Option<String>,
+}
+
+/// The seats whose input dismisses the overlay, from `"any"`, `"all"` or a seat name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum DismissSeat {
+    /// Input on any seat hides it.
+    #[default]
+    Any,
+    /// Every seat with a keyboard or pointer has to dismiss it before it hides.
+    All,
+    /// Only input on the seat with this name, like `seat0`.
+    Named(String),
+}
+
+impl From<String> for DismissSeat {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "any" => Self::Any,
+            "all" => Self::All,
+            _ => Self::Named(s),
+        }
+    }
+}
+
+/// Settings a named theme can override, unset ones keep the top level value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub image_dir: Option<PathBuf>,
+    pub music_dir: Option<PathBuf>,
+    pub interval_secs: Option<u64>,
+    pub jitter_secs: Option<f64>,
+    pub duration_secs: Option<u64>,
+    #[serde(deserialize_with = "deserialize_optional_color")]
+    pub background: Option<Rgba<u8>>,
+    pub volume: Option<f32>,
+}
+
+/// The audio paired with an image in `pairs.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PairedAudio {
+    Clip(PathBuf),
+    /// Played one after the other.
+    Sequence(Vec<PathBuf>),
+}
+
+/// What decides how long an overlay stays up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationMode {
+    /// `duration_secs`.
+    #[default]
+    Fixed,
+    /// Until the audio clip ends, capped at `max_duration_secs`. Falls back to `duration_secs`
+    /// when there's no clip to follow.
+    Audio,
+}
+
+/// The fill drawn behind the image, based on `background`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Backdrop {
+    /// `background` everywhere.
+    #[default]
+    Solid,
+    /// `background` at the top blending into `bottom`.
+    VerticalGradient {
+        #[serde(deserialize_with = "deserialize_color")]
+        bottom: Rgba<u8>,
+    },
+    /// `background` darkening towards the corners, `strength` from 0.0 (not at all) to 1.0
+    /// (black corners).
+    Vignette { strength: f32 },
+}
+
+/// How a new overlay replaces one that's still up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    /// Hide the old overlay, then show the new one.
+    #[default]
+    None,
+    /// Blend from the old image into the new one over `transition_ms`.
+    Crossfade,
+}
+
+/// What happens to a clip that's still playing when the next show starts its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioOverlapPolicy {
+    /// Play the new clip once the current one ends, looping clips are replaced instead.
+    Queue,
+    /// Stop the current clip and play the new one.
+    #[default]
+    Replace,
+    /// Skip the new clip while the current one is still audible.
+    Ignore,
+    /// Play the new clip over the current one, up to `max_concurrent_sounds` at once.
+    Mix,
+}
+
+/// What happens to the playing clip when the overlay hides.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HideAudioBehavior {
+    /// Fade it out and drop it.
+    #[default]
+    Stop,
+    /// Fade it out and pause it, the next show picks it back up unless its image comes with
+    /// another clip.
+    Pause,
+}
+
+/// How the image is sized relative to the overlay surface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    /// Draw the image at its native size, cropping it if it doesn't fit.
+    Center,
+    /// Shrink the image until it fits, preserving its aspect ratio.
+    #[default]
+    Fit,
+    /// Scale the image until it covers the whole surface, preserving its aspect ratio.
+    Fill,
+    /// Scale the image to exactly the surface size.
+    Stretch,
+}
+
+/// The resampling filter used to scale images.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleFilter {
+    /// Fastest, and keeps pixel art crisp.
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    /// Sharpest, and slowest.
+    Lanczos3,
+}
+
+/// A rotation or mirroring applied to the image before it's scaled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageTransform {
+    /// Leave the image as is.
+    #[default]
+    None,
+    /// Clockwise.
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+}
+
+/// Which layer shell layer the overlay goes on, from bottom to top.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayLayer {
+    Background,
+    Bottom,
+    #[default]
+    Top,
+    /// Above fullscreen windows too.
+    Overlay,
+}
+
+/// How the image moves while the overlay is up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Animation {
+    /// Keep the image centered.
+    #[default]
+    None,
+    /// Bounce the image off the edges of the overlay.
+    Bounce,
+}
+
+/// How a fade speeds up and slows down over its duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts slow and speeds up.
+    EaseIn,
+    /// Starts fast and slows down.
+    EaseOut,
+    /// Slow at both ends.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the linear progress `t`, from 0.0 to 1.0, onto the curve.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Self::EaseInOut => 1.0 - 2.0 * (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+/// How the images of a collage are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    /// A cell each, in a grid filling the overlay.
+    #[default]
+    Grid,
+    /// Sized like in a grid, but dropped anywhere, even partly off the edges.
+    Random,
+}
+
+/// Where the overlay sits on the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Position {
+    #[default]
+    Fullscreen,
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: None,
+            themes: HashMap::new(),
+            image_dir: default_media_dir("images"),
+            music_dir: default_media_dir("music"),
+            image_playlist: None,
+            music_playlist: None,
+            packs_dir: None,
+            source: None,
+            pack: None,
+            startup_delay_secs: 2,
+            interval_secs: 5,
+            jitter_secs: 0.0,
+            cooldown_secs: 0,
+            max_shows_per_minute: None,
+            duration_secs: 5,
+            show_on_start: false,
+            trigger_chance: 1.0,
+            seed: None,
+            active_hours: Vec::new(),
+            mute_hours: Vec::new(),
+            schedule: Vec::new(),
+            duration: DurationMode::default(),
+            max_duration_secs: 60,
+            scale: ScaleMode::default(),
+            scale_filter: ScaleFilter::default(),
+            min_image_size: None,
+            max_image_size: None,
+            background: Rgba([128, 128, 128, 196]),
+            backdrop: Backdrop::default(),
+            letterbox_color: None,
+            show_caption: false,
+            caption_font: None,
+            caption_size: 24.0,
+            caption_color: Rgba([255, 255, 255, 255]),
+            image_opacity: 1.0,
+            image_tint: None,
+            transforms: Vec::new(),
+            animation: Animation::default(),
+            transition: Transition::default(),
+            transition_ms: 300,
+            bounce_speed: 200.0,
+            volume: 1.0,
+            normalize: false,
+            loop_audio: false,
+            fade_in_ms: 150,
+            fade_out_ms: 150,
+            audio_overlap: AudioOverlapPolicy::default(),
+            hide_audio_behavior: HideAudioBehavior::default(),
+            max_concurrent_sounds: 4,
+            no_audio: false,
+            silent_chance: 0.0,
+            fade_ms: 150,
+            fade_easing: Easing::default(),
+            ignore: Vec::new(),
+            shuffle_images: false,
+            per_output_images: false,
+            images_per_show: 1,
+            placement: Placement::default(),
+            burst_count: 1,
+            burst_frame_ms: 150,
+            image_cache_size: 16,
+            max_image_pixels: 50_000_000,
+            outputs: Vec::new(),
+            output_pan: HashMap::new(),
+            position: Position::default(),
+            margin: 0,
+            width: 480,
+            height: 480,
+            layer: OverlayLayer::default(),
+            exclusive_zone: -1,
+            dismiss_on_key: false,
+            dismiss_on_click: false,
+            dismiss_seat: DismissSeat::default(),
+            snapshot_path: env::temp_dir().join("phonk-wl-{output}.png"),
+            on_show_cmd: None,
+            on_hide_cmd: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads `$XDG_CONFIG_HOME/phonk-wl/config.toml` (or `~/.config/phonk-wl/config.toml`),
+    /// falling back to the defaults if it doesn't exist.
+    pub fn load() -> Result<Self> {
+        let Some((path, contents)) = read_config_file("config.toml")? else {
+            return Ok(Self::default());
+        };
+
+        toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Loads `pairs.toml` from the config directory, which maps image file names to the audio
+    /// file, or files to play in order, that should always play alongside them.
+    pub fn load_pairs(&self) -> Result<HashMap<PathBuf, Vec<PathBuf>>> {
+        let Some((path, contents)) = read_config_file("pairs.toml")? else {
+            return Ok(HashMap::new());
+        };
+
+        let pairs: HashMap<PathBuf, PairedAudio> =
+            toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+
+        Ok(self.resolve_pairs(pairs))
+    }
+
+    /// Loads `weights.toml` from the config directory, which maps image file names to how
+    /// likely they are to be picked relative to the others.
+    pub fn load_weights(&self) -> Result<HashMap<PathBuf, u32>> {
+        let Some((path, contents)) = read_config_file("weights.toml")? else {
+            return Ok(HashMap::new());
+        };
+
+        let weights: HashMap<PathBuf, i64> =
+            toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+
+        self.resolve_weights(weights)
+            .with_context(|| format!("in {}", path.display()))
+    }
+
+    fn resolve_weights(&self, weights: HashMap<PathBuf, i64>) -> Result<HashMap<PathBuf, u32>> {
+        weights
+            .into_iter()
+            .map(|(image, weight)| {
+                let Ok(weight) = u32::try_from(weight) else {
+                    bail!(
+                        "weight {weight} for {} must be between 0 and {}",
+                        image.display(),
+                        u32::MAX
+                    );
+                };
+
+                Ok((self.image_dir.join(image), weight))
+            })
+            .collect()
+    }
+
+    fn resolve_pairs(
+        &self,
+        pairs: HashMap<PathBuf, PairedAudio>,
+    ) -> HashMap<PathBuf, Vec<PathBuf>> {
+        pairs
+            .into_iter()
+            .map(|(image, audio)| {
+                let clips = match audio {
+                    PairedAudio::Clip(clip) => vec![clip],
+                    PairedAudio::Sequence(clips) => clips,
+                };
+                let clips: Vec<PathBuf> =
+                    clips.iter().map(|clip| self.music_dir.join(clip)).collect();
+                (self.image_dir.join(image), clips)
+            })
+            .filter(|(_, clips)| !clips.is_empty())
+            .collect()
+    }
+
+    pub fn ignore_patterns(&self) -> Result<Vec<Pattern>> {
+        self.ignore
+            .iter()
+            .map(|glob| Pattern::new(glob).with_context(|| format!("invalid ignore glob {glob:?}")))
+            .collect()
+    }
+
+    /// Applies the theme called `name`, or the one set by `theme` without a name. Without
+    /// either the top level settings are used as they are.
+    pub fn apply_theme(&mut self, name: Option<&str>) -> Result<()> {
+        let Some(name) = name.or(self.theme.as_deref()) else {
+            return Ok(());
+        };
+
+        let Some(theme) = self.themes.get(name).cloned() else {
+            let mut names: Vec<&str> = self.themes.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            bail!("no theme named {name:?}, the config has {names:?}");
+        };
+
+        if let Some(dir) = theme.image_dir {
+            self.image_dir = dir;
+        }
+        if let Some(dir) = theme.music_dir {
+            self.music_dir = dir;
+        }
+        if let Some(interval) = theme.interval_secs {
+            self.interval_secs = interval;
+        }
+        if let Some(jitter) = theme.jitter_secs {
+            self.jitter_secs = jitter;
+        }
+        if let Some(duration) = theme.duration_secs {
+            self.duration_secs = duration;
+        }
+        if let Some(background) = theme.background {
+            self.background = background;
+        }
+        if let Some(volume) = theme.volume {
+            self.volume = volume;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the media directories absolute, so nothing depends on the working directory once
+    /// the config is loaded.
+    pub fn resolve_paths(&mut self) -> Result<()> {
+        if let Some(source) = &self.source {
+            let cache = source_cache_dir(source);
+            self.image_dir = cache.join("images");
+            self.music_dir = cache.join("music");
+        }
+
+        self.image_dir = std::path::absolute(&self.image_dir)?;
+        self.music_dir = std::path::absolute(&self.music_dir)?;
+        self.snapshot_path = std::path::absolute(&self.snapshot_path)?;
+        for path in [
+            &mut self.image_playlist,
+            &mut self.music_playlist,
+            &mut self.packs_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = std::path::absolute(&*path)?;
+        }
+
+        for show in &mut self.schedule {
+            show.image = self.image_dir.join(&show.image);
+            if let Some(audio) = &mut show.audio {
+                *audio = self.music_dir.join(&*audio);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.source.is_some() {
+            if !cfg!(feature = "http") {
+                bail!("source needs phonk-wl built with --features http");
+            }
+
+            if self.packs_dir.is_some()
+                || self.image_playlist.is_some()
+                || self.music_playlist.is_some()
+            {
+                bail!("source can't be combined with packs_dir or playlists");
+            }
+        }
+
+        if let Some(packs_dir) = &self.packs_dir {
+            if !packs_dir.is_dir() {
+                bail!(
+                    "packs directory {} does not exist or is not a directory",
+                    packs_dir.display()
+                );
+            }
+
+            if let Some(pack) = &self.pack
+                && !packs_dir.join(pack).is_dir()
+            {
+                bail!("no pack named {pack:?} in {}", packs_dir.display());
+            }
+            if self.image_playlist.is_some() || self.music_playlist.is_some() {
+                bail!("packs_dir can't be combined with image_playlist or music_playlist");
+            }
+        } else if self.pack.is_some() {
+            bail!("pack needs packs_dir to be set");
+        }
+
+        for playlist in [&self.image_playlist, &self.music_playlist]
+            .into_iter()
+            .flatten()
+        {
+            if !playlist.is_file() {
+                bail!(
+                    "playlist {} does not exist or is not a file",
+                    playlist.display()
+                );
+            }
+        }
+
+        if self.packs_dir.is_none() && self.image_playlist.is_none() && !self.image_dir.is_dir() {
+            bail!(
+                "image directory {} does not exist or is not a directory",
+                self.image_dir.display()
+            );
+        }
+
+        if self.packs_dir.is_none() && self.music_playlist.is_none() && !self.music_dir.is_dir() {
+            bail!(
+                "music directory {} does not exist or is not a directory",
+                self.music_dir.display()
+            );
+        }
+
+        if self.position != Position::Fullscreen && (self.width == 0 || self.height == 0) {
+            bail!("width and height must be non-zero unless position is fullscreen");
+        }
+
+        if !(1..=MAX_IMAGES_PER_SHOW).contains(&self.images_per_show) {
+            bail!("images_per_show must be from 1 to {MAX_IMAGES_PER_SHOW}");
+        }
+
+        if !self.jitter_secs.is_finite() || self.jitter_secs < 0.0 {
+            bail!("jitter_secs must be 0 or more");
+        }
+
+        if !(0.0..=1.0).contains(&self.trigger_chance) {
+            bail!("trigger_chance must be between 0.0 and 1.0");
+        }
+
+        if self.max_concurrent_sounds == 0 {
+            bail!("max_concurrent_sounds must be at least 1");
+        }
+
+        if self.show_caption && self.caption_font.is_none() {
+            bail!("show_caption needs caption_font to be set");
+        }
+
+        if !self.caption_size.is_finite() || self.caption_size <= 0.0 {
+            bail!("caption_size must be more than 0");
+        }
+
+        if self.max_shows_per_minute == Some(0) {
+            bail!("max_shows_per_minute must be at least 1");
+        }
+
+        if !(0.0..=1.0).contains(&self.silent_chance) {
+            bail!("silent_chance must be between 0.0 and 1.0");
+        }
+
+        for (output, pan) in &self.output_pan {
+            if !(-1.0..=1.0).contains(pan) {
+                bail!("output_pan for {output:?} must be between -1.0 and 1.0");
+            }
+        }
+
+        for size in [self.min_image_size, self.max_image_size]
+            .into_iter()
+            .flatten()
+        {
+            if size.is_nan() || size <= 0.0 {
+                bail!("min_image_size and max_image_size must be greater than 0");
+            }
+        }
+
+        if self.max_image_pixels == 0 {
+            bail!("max_image_pixels must be at least 1");
+        }
+
+        if let (Some(min), Some(max)) = (self.min_image_size, self.max_image_size)
+            && min > max
+        {
+            bail!("min_image_size must not be larger than max_image_size");
+        }
+
+        self.ignore_patterns()?;
+
+        Ok(())
+    }
+}
+
+/// `$XDG_DATA_HOME/phonk-wl/<name>` (or `~/.local/share/phonk-wl/<name>`) if it exists,
+/// otherwise `<name>` in the working directory.
+fn default_media_dir(name: &str) -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .map(|data_home| data_home.join("phonk-wl").join(name))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Where the files from `source` are downloaded to, `$XDG_CACHE_HOME/phonk-wl/remote/<source>`
+/// (or under `~/.cache`), with the URL flattened into a directory name.
+fn source_cache_dir(source: &str) -> PathBuf {
+    let name: String = source
+        .split_once("://")
+        .map_or(source, |(_, rest)| rest)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from(".cache"))
+        .join("phonk-wl/remote")
+        .join(name)
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("phonk-wl"))
+}
+
+/// Reads `name` from the config directory, returning `None` if it doesn't exist.
+fn read_config_file(name: &str) -> Result<Option<(PathBuf, String)>> {
+    let Some(path) = config_dir().map(|dir| dir.join(name)) else {
+        return Ok(None);
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some((path, contents))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rgba<u8>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_hex_color(&value).map_err(D::Error::custom)
+}
+
+fn deserialize_optional_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Rgba<u8>>, D::Error> {
+    deserialize_color(deserializer).map(Some)
+}
+
+/// Parses `#rrggbb` or `#rrggbbaa` (the `#` is optional), alpha defaults to opaque.
+pub fn parse_hex_color(value: &str) -> Result<Rgba<u8>> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    if !matches!(hex.len(), 6 | 8) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("invalid color {value:?}, expected #rrggbb or #rrggbbaa");
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    let alpha = if hex.len() == 8 { channel(6) } else { 255 };
+
+    Ok(Rgba([channel(0), channel(2), channel(4), alpha]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(
+            parse_hex_color("#808080c4").unwrap(),
+            Rgba([128, 128, 128, 196])
+        );
+        assert_eq!(parse_hex_color("ff0000").unwrap(), Rgba([255, 0, 0, 255]));
+        assert_eq!(parse_hex_color("#00000000").unwrap(), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        for value in ["", "#fff", "#12345", "#gggggg", "#1234567890"] {
+            assert!(parse_hex_color(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn media_dirs_resolve_to_absolute_paths() {
+        let mut config = Config {
+            image_dir: PathBuf::from("images"),
+            music_dir: PathBuf::from("/media/music"),
+            packs_dir: Some(PathBuf::from("packs")),
+            ..Config::default()
+        };
+        config.resolve_paths().unwrap();
+
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(config.image_dir, cwd.join("images"));
+        assert_eq!(config.music_dir, PathBuf::from("/media/music"));
+        assert_eq!(config.packs_dir, Some(cwd.join("packs")));
+    }
+
+    #[test]
+    fn pairs_resolve_against_media_dirs() {
+        let config = Config {
+            image_dir: PathBuf::from("/media/images"),
+            music_dir: PathBuf::from("/media/music"),
+            ..Config::default()
+        };
+        let pairs = toml::from_str(
+            r#"
+            "troll.png" = "ef3.ogg"
+            "skull.png" = ["boom.ogg", "scream.ogg"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.resolve_pairs(pairs),
+            HashMap::from([
+                (
+                    PathBuf::from("/media/images/troll.png"),
+                    vec![PathBuf::from("/media/music/ef3.ogg")]
+                ),
+                (
+                    PathBuf::from("/media/images/skull.png"),
+                    vec![
+                        PathBuf::from("/media/music/boom.ogg"),
+                        PathBuf::from("/media/music/scream.ogg")
+                    ]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn weights_must_be_non_negative() {
+        let config = Config {
+            image_dir: PathBuf::from("/media/images"),
+            ..Config::default()
+        };
+
+        let weights = toml::from_str(r#""troll.png" = 3"#).unwrap();
+        assert_eq!(
+            config.resolve_weights(weights).unwrap(),
+            HashMap::from([(PathBuf::from("/media/images/troll.png"), 3)])
+        );
+
+        let weights = toml::from_str(r#""troll.png" = -1"#).unwrap();
+        assert!(config.resolve_weights(weights).is_err());
+    }
+
+    #[test]
+    fn backdrop_is_read_from_toml() {
+        let config: Config = toml::from_str(
+            r##"
+            [backdrop]
+            kind = "vertical-gradient"
+            bottom = "#000000"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.backdrop,
+            Backdrop::VerticalGradient {
+                bottom: Rgba([0, 0, 0, 255])
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_layers_are_rejected() {
+        let config: Config = toml::from_str(r#"layer = "overlay""#).unwrap();
+        assert_eq!(config.layer, OverlayLayer::Overlay);
+
+        assert!(toml::from_str::<Config>(r#"layer = "sky""#).is_err());
+    }
+
+    #[test]
+    fn background_is_read_from_toml() {
+        let config: Config = toml::from_str(r##"background = "#10203040""##).unwrap();
+        assert_eq!(config.background, Rgba([0x10, 0x20, 0x30, 0x40]));
+    }
+
+    #[test]
+    fn transforms_are_read_from_toml() {
+        let config: Config =
+            toml::from_str(r#"transforms = ["none", "rotate90", "flip-horizontal"]"#).unwrap();
+        assert_eq!(
+            config.transforms,
+            [
+                ImageTransform::None,
+                ImageTransform::Rotate90,
+                ImageTransform::FlipHorizontal
+            ]
+        );
+    }
+
+    #[test]
+    fn dismiss_seats_are_read_from_toml() {
+        for (value, seat) in [
+            ("all", DismissSeat::All),
+            ("seat1", DismissSeat::Named("seat1".to_string())),
+        ] {
+            let config: Config = toml::from_str(&format!("dismiss_seat = {value:?}")).unwrap();
+            assert_eq!(config.dismiss_seat, seat);
+        }
+        assert_eq!(Config::default().dismiss_seat, DismissSeat::Any);
+    }
+
+    #[test]
+    fn easings_run_from_zero_to_one() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0, "{easing:?}");
+            assert_eq!(easing.apply(1.0), 1.0, "{easing:?}");
+            assert_eq!(easing.apply(2.0), 1.0, "{easing:?}");
+        }
+
+        assert_eq!(Easing::Linear.apply(0.25), 0.25);
+        assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+        assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+        assert_eq!(Easing::EaseInOut.apply(0.25), 0.125);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.75), 0.875);
+    }
+
+    #[test]
+    fn scale_filters_are_read_from_toml() {
+        let config: Config = toml::from_str(r#"scale_filter = "catmull-rom""#).unwrap();
+        assert_eq!(config.scale_filter, ScaleFilter::CatmullRom);
+
+        assert!(toml::from_str::<Config>(r#"scale_filter = "bicubic""#).is_err());
+    }
+
+    #[test]
+    fn themes_override_the_top_level() {
+        let mut config: Config = toml::from_str(
+            r##"
+            theme = "chaos"
+            volume = 0.5
+            interval_secs = 60
+
+            [themes.chaos]
+            interval_secs = 1
+            background = "#ff0000"
+
+            [themes.work]
+            volume = 0.1
+            "##,
+        )
+        .unwrap();
+
+        let mut work = config.clone();
+        work.apply_theme(Some("work")).unwrap();
+        assert_eq!((work.volume, work.interval_secs), (0.1, 60));
+
+        config.apply_theme(None).unwrap();
+        assert_eq!((config.volume, config.interval_secs), (0.5, 1));
+        assert_eq!(config.background, Rgba([255, 0, 0, 255]));
+
+        let e = config.apply_theme(Some("party")).err().unwrap();
+        assert!(format!("{e}").contains(r#"["chaos", "work"]"#), "{e}");
+    }
+}
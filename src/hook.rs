@@ -0,0 +1,102 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use log::{debug, warn};
+
+use crate::status::Status;
+
+/// User commands run on every show and hide, through `sh -c`.
+#[derive(Debug)]
+pub struct Hooks {
+    pub on_show: Option<String>,
+    pub on_hide: Option<String>,
+}
+
+impl Hooks {
+    /// Starts the command for `status`, if there is one, without waiting for it.
+    pub fn run(&self, status: &Status) {
+        let Some(mut command) = self.command(status) else {
+            return;
+        };
+
+        match command.stdin(Stdio::null()).spawn() {
+            Ok(mut child) => {
+                // reaped off the event loop, so a slow hook doesn't hold up the overlay
+                thread::spawn(move || match child.wait() {
+                    Ok(exit) if !exit.success() => warn!("hook exited with {exit}"),
+                    Ok(_) => {}
+                    Err(e) => warn!("waiting for a hook: {e}"),
+                });
+            }
+            Err(e) => warn!("couldn't run hook: {e}"),
+        }
+    }
+
+    /// The command for `status`. A show passes its image and audio in `PHONK_IMAGE` and
+    /// `PHONK_AUDIO`, and as `$1` and `$2`, empty when there's none.
+    fn command(&self, status: &Status) -> Option<Command> {
+        let (line, image, audio) = match status {
+            Status::Show { image, audio } => {
+                (self.on_show.as_ref()?, image.as_deref(), audio.as_deref())
+            }
+            Status::Hide => (self.on_hide.as_ref()?, None, None),
+        };
+        debug!("running hook {line:?}");
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(line).arg("phonk-wl");
+
+        for (name, path) in [("PHONK_IMAGE", image), ("PHONK_AUDIO", audio)] {
+            match path {
+                Some(path) => command.env(name, path),
+                None => command.env_remove(name),
+            };
+            command.arg(path.unwrap_or(Path::new("")));
+        }
+
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, path::PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn shows_pass_their_media_along() {
+        let hooks = Hooks {
+            on_show: Some("notify-send \"$PHONK_IMAGE\"".to_string()),
+            on_hide: None,
+        };
+        let show = Status::Show {
+            image: Some(PathBuf::from("/images/skull.png")),
+            audio: None,
+        };
+
+        let command = hooks.command(&show).unwrap();
+        let args: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-c",
+                "notify-send \"$PHONK_IMAGE\"",
+                "phonk-wl",
+                "/images/skull.png",
+                ""
+            ]
+        );
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&(
+            OsStr::new("PHONK_IMAGE"),
+            Some(OsStr::new("/images/skull.png"))
+        )));
+        assert!(envs.contains(&(OsStr::new("PHONK_AUDIO"), None)));
+
+        assert!(hooks.command(&Status::Hide).is_none());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    env,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, warn};
+use smithay_client_toolkit::reexports::calloop::{
+    Interest, LoopHandle, Mode, PostAction, generic::Generic,
+};
+
+use crate::{app::App, config::OverlayLayer};
+
+/// Longest command line accepted before the connection is dropped.
+const MAX_LINE: usize = 1024;
+
+/// A command received over the control socket, one per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Show,
+    Hide,
+    /// Replace the current overlay with a new pick, or show one if hidden.
+    Next,
+    /// Forget cached images so changes on disk are picked up.
+    Reload,
+    /// Silence the audio until `unmute`, overlays keep showing.
+    Mute,
+    Unmute,
+    /// Move the overlay to another layer, like below fullscreen windows while gaming.
+    Layer(OverlayLayer),
+    /// Show the next overlay only on the output with this name or description.
+    Output(String),
+    /// Save what the overlay currently shows to PNG files.
+    Snapshot,
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "show" => Ok(Self::Show),
+            "hide" => Ok(Self::Hide),
+            "next" => Ok(Self::Next),
+            "reload" => Ok(Self::Reload),
+            "mute" => Ok(Self::Mute),
+            "unmute" => Ok(Self::Unmute),
+            "snapshot" => Ok(Self::Snapshot),
+            other => match other.split_once(' ') {
+                Some(("layer", layer)) => Ok(Self::Layer(parse_layer(layer.trim())?)),
+                Some(("output", name)) => Ok(Self::Output(name.trim().to_string())),
+                _ => bail!("unknown command {other:?}"),
+            },
+        }
+    }
+}
+
+impl Command {
+    fn run(self, app: &mut App) -> Result<()> {
+        match self {
+            Self::Show => app.show(),
+            Self::Hide => app.hide(),
+            Self::Next => app.next_overlay(),
+            Self::Reload => app.reload_media(),
+            Self::Mute => app.set_muted(true),
+            Self::Unmute => app.set_muted(false),
+            Self::Layer(layer) => app.set_layer(layer),
+            Self::Output(name) => app.set_target_output(name),
+            Self::Snapshot => return app.snapshot(),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_layer(s: &str) -> Result<OverlayLayer> {
+    match s {
+        "background" => Ok(OverlayLayer::Background),
+        "bottom" => Ok(OverlayLayer::Bottom),
+        "top" => Ok(OverlayLayer::Top),
+        "overlay" => Ok(OverlayLayer::Overlay),
+        other => bail!("unknown layer {other:?}, expected background, bottom, top or overlay"),
+    }
+}
+
+fn socket_path() -> Result<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .ok_or_else(|| anyhow!("XDG_RUNTIME_DIR is not set"))?;
+
+    Ok(PathBuf::from(runtime_dir).join("phonk-wl.sock"))
+}
+
+/// The bound control socket, removed again when this is dropped.
+pub struct Socket(PathBuf);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Binds the control socket and registers it with the event loop. Every connection accepts
+/// newline-delimited commands and gets an `ok` or `error: ...` line back for each of them.
+pub fn listen(loop_handle: &LoopHandle<'static, App>) -> Result<Socket> {
+    let path = socket_path()?;
+    remove_stale(&path)?;
+
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("binding {}", path.display()))?;
+    let socket = Socket(path);
+    listener.set_nonblocking(true)?;
+
+    let clients = loop_handle.clone();
+    loop_handle
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, _| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = add_client(&clients, stream) {
+                                warn!("dropping control connection: {e:#}");
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow!("inserting control socket: {e}"))?;
+
+    Ok(socket)
+}
+
+/// Removes the socket a previous instance that didn't shut down cleanly left behind at `path`.
+/// Fails if an instance is still listening on it.
+fn remove_stale(path: &Path) -> Result<()> {
+    match UnixStream::connect(path) {
+        Ok(_) => bail!("another instance is listening on {}", path.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
+        Err(e) => return Err(e).with_context(|| format!("connecting to {}", path.display())),
+    }
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn add_client(loop_handle: &LoopHandle<'static, App>, stream: UnixStream) -> Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut pending = Vec::new();
+
+    loop_handle
+        .insert_source(
+            Generic::new(stream, Interest::READ, Mode::Level),
+            move |_, stream, app| {
+                let mut stream: &UnixStream = stream;
+                let mut buf = [0; 512];
+
+                loop {
+                    match stream.read(&mut buf) {
+                        Ok(0) => return Ok(PostAction::Remove),
+                        Ok(n) => pending.extend_from_slice(&buf[..n]),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(_) => return Ok(PostAction::Remove),
+                    }
+                }
+
+                if pending.len() > MAX_LINE && !pending.contains(&b'\n') {
+                    let _ = stream.write_all(b"error: line too long\n");
+                    return Ok(PostAction::Remove);
+                }
+
+                while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line);
+
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let reply = match line.parse::<Command>().and_then(|command| {
+                        debug!("control command {command:?}");
+                        command.run(app)
+                    }) {
+                        Ok(()) => "ok\n".to_string(),
+                        Err(e) => format!("error: {e:#}\n"),
+                    };
+
+                    if stream.write_all(reply.as_bytes()).is_err() {
+                        return Ok(PostAction::Remove);
+                    }
+                }
+
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow!("inserting control connection: {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let path = std::env::temp_dir().join(format!("phonk-wl-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        remove_stale(&path).unwrap();
+
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(remove_stale(&path).is_err());
+        assert!(path.exists());
+
+        drop(listener);
+        remove_stale(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!("show".parse::<Command>().unwrap(), Command::Show);
+        assert_eq!("hide\n".parse::<Command>().unwrap(), Command::Hide);
+        assert_eq!(" next ".parse::<Command>().unwrap(), Command::Next);
+        assert_eq!("reload\r\n".parse::<Command>().unwrap(), Command::Reload);
+        assert_eq!("mute".parse::<Command>().unwrap(), Command::Mute);
+        assert_eq!("unmute".parse::<Command>().unwrap(), Command::Unmute);
+        assert_eq!("snapshot".parse::<Command>().unwrap(), Command::Snapshot);
+        assert_eq!(
+            "layer bottom\n".parse::<Command>().unwrap(),
+            Command::Layer(OverlayLayer::Bottom)
+        );
+        assert_eq!(
+            "output Dell Inc. U2720Q\n".parse::<Command>().unwrap(),
+            Command::Output("Dell Inc. U2720Q".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!("explode".parse::<Command>().is_err());
+        assert!("SHOW".parse::<Command>().is_err());
+        assert!("layer attic".parse::<Command>().is_err());
+        assert!("output".parse::<Command>().is_err());
+    }
+}
@@ -1,53 +1,388 @@
-use std::time::Duration;
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use log::{info, warn};
 use smithay_client_toolkit::reexports::{
-    calloop::timer::{TimeoutAction, Timer},
+    calloop::{
+        signals::{Signal, Signals},
+        timer::{TimeoutAction, Timer},
+    },
     calloop_wayland_source::WaylandSource,
 };
 use smithay_client_toolkit::{
     compositor::CompositorState, output::OutputState, reexports::calloop::EventLoop,
-    registry::RegistryState, shell::wlr_layer::LayerShell, shm::Shm,
+    registry::RegistryState, seat::SeatState, shell::wlr_layer::LayerShell, shm::Shm,
+};
+use wayland_client::{
+    Connection, QueueHandle, backend::WaylandError, globals::registry_queue_init,
 };
-use wayland_client::{Connection, QueueHandle, globals::registry_queue_init};
 
-use crate::app::App;
+use crate::{app::App, config::Config};
 
 mod app;
+mod cache;
+mod caption;
+mod check;
+mod config;
+mod hook;
+mod ipc;
+mod outputs;
+mod pack;
+mod playlist;
+#[cfg(feature = "http")]
+mod remote;
+mod render;
+mod schedule;
+mod sound;
+mod status;
+#[cfg(feature = "systemd")]
+mod systemd;
+mod version;
+mod watch;
+
+/// Wait before the first attempt to reconnect to a compositor that went away, doubled for
+/// every attempt after it up to `RECONNECT_MAX`.
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+/// How long a session has to stay up for the backoff to start over once it's lost.
+const STABLE_SESSION: Duration = Duration::from_secs(60);
+
+#[derive(Parser)]
+#[command(version = version::LONG, about)]
+struct Cli {
+    /// Directory to pick overlay images from
+    #[arg(long, value_name = "DIR")]
+    images: Option<PathBuf>,
+
+    /// Directory to pick audio clips from
+    #[arg(long, value_name = "DIR")]
+    music: Option<PathBuf>,
+
+    /// File listing the images to pick from, one path per line, instead of a directory
+    #[arg(long, value_name = "FILE")]
+    playlist: Option<PathBuf>,
+
+    /// File listing the audio clips to pick from, one path per line, instead of a directory
+    #[arg(long, value_name = "FILE")]
+    music_playlist: Option<PathBuf>,
+
+    /// Seconds to wait before the first overlay
+    #[arg(long, value_name = "SECS")]
+    startup_delay: Option<u64>,
+
+    /// Seconds to wait between overlays
+    #[arg(long, value_name = "SECS")]
+    interval: Option<u64>,
+
+    /// Seconds an overlay stays on screen
+    #[arg(long, value_name = "SECS")]
+    show_duration: Option<u64>,
+
+    /// Playback volume from 0.0 to 1.0
+    #[arg(long)]
+    volume: Option<f32>,
+
+    /// Show overlays without opening an audio device
+    #[arg(long)]
+    no_audio: bool,
+
+    /// Theme from the config to use instead of the one it sets
+    #[arg(long, value_name = "NAME")]
+    theme: Option<String>,
+
+    /// Seed for every random choice, the same seed and media replay the same session
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Show a single overlay and exit
+    #[arg(long)]
+    once: bool,
+
+    /// Print a JSON line to stdout whenever the overlay shows or hides
+    #[arg(long)]
+    status_json: bool,
+
+    /// Print the outputs the compositor advertises and exit
+    #[arg(long)]
+    list_outputs: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Try decoding every image and audio file in the media directories and report the broken
+    /// ones, without showing anything
+    Check,
+    /// Draw an image the way a show would and save it as a PNG, without a compositor
+    Render {
+        /// Image to draw
+        #[arg(long, value_name = "FILE")]
+        image: PathBuf,
+        /// Where to save the frame
+        #[arg(long, value_name = "FILE", default_value = "frame.png")]
+        out: PathBuf,
+        /// Size of the frame
+        #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1920x1080", value_parser = render::parse_size)]
+        size: (u32, u32),
+    },
+}
 
 fn main() -> Result<()> {
-    let conn = Connection::connect_to_env()?;
-    let (globals, event_queue) = registry_queue_init(&conn)?;
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let cli = Cli::parse();
+
+    if cli.list_outputs {
+        return outputs::list(&Connection::connect_to_env()?);
+    }
+
+    let mut config = Config::load()?;
+    config.apply_theme(cli.theme.as_deref())?;
+
+    if let Some(images) = cli.images {
+        config.image_dir = images;
+    }
+
+    if let Some(music) = cli.music {
+        config.music_dir = music;
+    }
+
+    if let Some(playlist) = cli.playlist {
+        config.image_playlist = Some(playlist);
+    }
+
+    if let Some(playlist) = cli.music_playlist {
+        config.music_playlist = Some(playlist);
+    }
+
+    if let Some(delay) = cli.startup_delay {
+        config.startup_delay_secs = delay;
+    }
+
+    if let Some(interval) = cli.interval {
+        config.interval_secs = interval;
+    }
+
+    if let Some(show_duration) = cli.show_duration {
+        config.duration_secs = show_duration;
+    }
+
+    if let Some(volume) = cli.volume {
+        config.volume = volume;
+    }
+
+    if let Some(seed) = cli.seed {
+        config.seed = Some(seed);
+    }
+
+    if cli.no_audio {
+        config.no_audio = true;
+    }
+
+    config.resolve_paths()?;
+
+    // previews only need the config, not the media directories
+    if let Some(Command::Render { image, out, size }) = &cli.command {
+        return render::run(&config, image, out, *size);
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(source) = &config.source {
+        remote::sync(source, &config.image_dir, &config.music_dir)?;
+    }
+
+    config.validate()?;
+
+    if let Some(Command::Check) = cli.command {
+        return check::run(&config);
+    }
+
+    let mut backoff = RECONNECT_MIN;
+    let mut reconnecting = false;
+
+    loop {
+        let conn = match Connection::connect_to_env() {
+            Ok(conn) => conn,
+            // the first connection has to work, later ones wait for the compositor to come back
+            Err(e) if !reconnecting => return Err(e.into()),
+            Err(e) => {
+                warn!("reconnecting in {}s: {e}", backoff.as_secs());
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(RECONNECT_MAX);
+                continue;
+            }
+        };
+
+        let started = Instant::now();
+        match run_once(&conn, &config, cli.status_json, cli.once) {
+            Ok(()) => break,
+            Err(e) if connection_lost(&conn) => {
+                warn!("lost the connection to the compositor: {e:#}");
+                reconnecting = true;
+                // a compositor that keeps dropping the connection right away gets more and
+                // more time
+                if started.elapsed() >= STABLE_SESSION {
+                    backoff = RECONNECT_MIN;
+                }
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(RECONNECT_MAX);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    #[cfg(feature = "systemd")]
+    systemd::notify("STOPPING=1");
+
+    Ok(())
+}
+
+/// Sets up everything on `conn` and runs the event loop until a signal or `--once` stops it.
+fn run_once(conn: &Connection, config: &Config, status_json: bool, once: bool) -> Result<()> {
+    let (globals, mut event_queue) = registry_queue_init(conn)?;
     let qh: QueueHandle<App> = event_queue.handle();
 
     let compositor_state = CompositorState::bind(&globals, &qh)?;
     let layer_shell = LayerShell::bind(&globals, &qh)?;
     let output_state = OutputState::new(&globals, &qh);
     let registry_state = RegistryState::new(&globals);
+    let seat_state = SeatState::new(&globals, &qh);
     let shm = Shm::bind(&globals, &qh)?;
 
+    let mut event_loop: EventLoop<App> = EventLoop::try_new()?;
+    let loop_handle = event_loop.handle();
+
+    // this blocks the signals for the current thread, so it has to happen before the audio
+    // threads are spawned or they would get the default (fatal) SIGUSR1/SIGUSR2 handling instead
+    let signals = Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2])?;
+    let termination = Signals::new(&[Signal::SIGINT, Signal::SIGTERM])?;
+
     let mut app = App::new(
         output_state,
         layer_shell,
         shm,
         compositor_state,
         registry_state,
+        seat_state,
+        loop_handle.clone(),
+        qh,
+        config,
     )?;
 
-    let mut event_loop: EventLoop<App> = EventLoop::try_new()?;
-    let loop_handle = event_loop.handle();
+    if status_json {
+        app.print_status();
+    }
 
-    let wayland_source = WaylandSource::new(conn, event_queue);
+    // creates the surfaces for the outputs that are already there
+    event_queue.roundtrip(&mut app)?;
+
+    let wayland_source = WaylandSource::new(conn.clone(), event_queue);
     loop_handle.insert_source(wayland_source, |_, queue, app| queue.dispatch_pending(app))?;
-    let timer = Timer::from_duration(Duration::from_secs(2));
+    loop_handle.insert_source(signals, |event, _, app| match event.signal() {
+        Signal::SIGUSR2 => app.toggle_mute(),
+        _ => app.toggle_overlay(),
+    })?;
+
+    let loop_signal = event_loop.get_signal();
+    loop_handle.insert_source(termination, move |event, _, app| {
+        info!("received {:?}, shutting down", event.signal());
+        app.shutdown();
+        loop_signal.stop();
+    })?;
+
+    let _socket = ipc::listen(&loop_handle)
+        .inspect_err(|e| warn!("control socket disabled: {e:#}"))
+        .ok();
+
+    // kept alive for as long as the event loop runs, packs are read on every show instead and
+    // playlists on a reload
+    let watched: Vec<PathBuf> = [
+        (config.image_dir.clone(), &config.image_playlist),
+        (config.music_dir.clone(), &config.music_playlist),
+    ]
+    .into_iter()
+    .filter(|(_, playlist)| playlist.is_none())
+    .map(|(dir, _)| dir)
+    .collect();
+    let _watcher = match config.packs_dir {
+        Some(_) => None,
+        None if watched.is_empty() => None,
+        None => match watch::watch(&loop_handle, &watched) {
+            Ok(watcher) => {
+                app.watching_media();
+                Some(watcher)
+            }
+            Err(e) => {
+                warn!("not watching the media directories, listing them on every show: {e:#}");
+                None
+            }
+        },
+    };
+
+    // the timer's first tick sees the show's next_toggle and leaves it up for its duration
+    if config.show_on_start {
+        app.show_when_ready();
+    }
+
+    let trigger_chance = config.trigger_chance;
+    let active_hours = config.active_hours.clone();
+    let loop_signal = event_loop.get_signal();
+    let timer = Timer::from_duration(Duration::from_secs(config.startup_delay_secs));
     loop_handle
-        .insert_source(timer, |_deadline, _metadata, app| {
+        .insert_source(timer, move |deadline, _metadata, app| {
+            // a manual toggle since this was scheduled pushes the next automatic one back
+            if deadline < app.next_toggle() && !app.audio_finished() {
+                return TimeoutAction::ToInstant(app.next_wakeup());
+            }
+
+            // a recent show, manual or not, holds the next automatic one back
+            if !app.is_shown()
+                && let Some(until) = app.cooldown_until()
+            {
+                return TimeoutAction::ToInstant(until);
+            }
+
+            // outside the active hours the schedule keeps ticking, it just doesn't show anything
+            if !app.is_shown()
+                && (!schedule::is_active(&active_hours, Local::now().time())
+                    || !app.roll(trigger_chance)
+                    || app.throttled())
+            {
+                app.skip_show();
+                return TimeoutAction::ToInstant(app.next_wakeup());
+            }
+
             app.toggle_overlay();
-            TimeoutAction::ToDuration(Duration::from_secs(5))
+
+            if once && !app.is_shown() {
+                app.shutdown();
+                loop_signal.stop();
+                return TimeoutAction::Drop;
+            }
+
+            TimeoutAction::ToInstant(app.next_wakeup())
         })
         .unwrap();
 
+    #[cfg(feature = "systemd")]
+    systemd::notify("READY=1");
+
     event_loop.run(None, &mut app, |_| {})?;
+    conn.flush()?;
 
     Ok(())
 }
+
+/// Whether the session failed because `conn` died under it, like when the compositor
+/// restarts, rather than on its own.
+fn connection_lost(conn: &Connection) -> bool {
+    matches!(conn.flush(), Err(WaylandError::Io(_)))
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use smithay_client_toolkit::{
+    output::{OutputHandler, OutputInfo, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+use wayland_client::{
+    Connection, QueueHandle, globals::registry_queue_init, protocol::wl_output::WlOutput,
+};
+
+/// Just enough state to learn about the outputs, without creating any surfaces.
+struct OutputLister {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+/// Prints every output the compositor advertises, with the name and description the `outputs`
+/// config option matches against.
+pub fn list(conn: &Connection) -> Result<()> {
+    let (globals, mut event_queue) = registry_queue_init(conn)?;
+    let qh = event_queue.handle();
+
+    let mut lister = OutputLister {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+
+    // the first roundtrip binds the outputs, the second delivers their properties
+    event_queue.roundtrip(&mut lister)?;
+    event_queue.roundtrip(&mut lister)?;
+
+    for output in lister.output_state.outputs() {
+        if let Some(info) = lister.output_state.info(&output) {
+            println!("{}", describe(&info));
+        }
+    }
+
+    Ok(())
+}
+
+fn describe(info: &OutputInfo) -> String {
+    let name = info.name.as_deref().unwrap_or("(unnamed)");
+    let description = info.description.as_deref().unwrap_or("");
+    let resolution = info
+        .modes
+        .iter()
+        .find(|mode| mode.current)
+        .map(|mode| format!("{}x{}", mode.dimensions.0, mode.dimensions.1))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    format!(
+        "{name}\t{resolution} scale {}\t{description}",
+        info.scale_factor
+    )
+}
+
+impl OutputHandler for OutputLister {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: WlOutput) {}
+}
+
+impl ProvidesRegistryState for OutputLister {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+
+    registry_handlers![OutputState];
+}
+
+smithay_client_toolkit::delegate_output!(OutputLister);
+smithay_client_toolkit::delegate_registry!(OutputLister);
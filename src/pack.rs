@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use rand::RngCore;
+
+use crate::app::{is_supported_audio, is_supported_image, list_files};
+
+/// A subdirectory of `packs_dir`, a themed set of images and the audio that goes with them.
+#[derive(Debug)]
+pub struct Pack {
+    pub name: String,
+    pub path: PathBuf,
+    pub images: Vec<PathBuf>,
+    pub audio: Vec<PathBuf>,
+}
+
+impl Pack {
+    pub fn load(path: PathBuf, ignore: &[Pattern]) -> Result<Self> {
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            images: list_files(&path, is_supported_image, ignore)?,
+            audio: list_files(&path, is_supported_audio, ignore)?,
+            path,
+        })
+    }
+}
+
+/// The non-hidden subdirectories of `dir`, sorted by name.
+fn pack_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+        if !hidden && entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Loads every pack in `dir`.
+pub fn all(dir: &Path, ignore: &[Pattern]) -> Result<Vec<Pack>> {
+    pack_dirs(dir)?
+        .into_iter()
+        .map(|path| Pack::load(path, ignore))
+        .collect()
+}
+
+/// Loads the pack called `name` from `dir`, or a random one with at least one image.
+pub fn choose(
+    dir: &Path,
+    name: Option<&str>,
+    ignore: &[Pattern],
+    rng: &mut impl RngCore,
+) -> Result<Pack> {
+    if let Some(name) = name {
+        let path = dir.join(name);
+        if !path.is_dir() {
+            bail!("no pack named {name:?} in {}", dir.display());
+        }
+        return Pack::load(path, ignore);
+    }
+
+    let mut packs = all(dir, ignore)?;
+    packs.retain(|pack| !pack.images.is_empty());
+
+    if packs.is_empty() {
+        bail!("no packs with images in {}", dir.display());
+    }
+
+    let i = rng.next_u32() as usize % packs.len();
+    Ok(packs.swap_remove(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn temp_packs(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("phonk-wl-packs-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (pack, file) in [
+            ("spooky", "skull.png"),
+            ("spooky", "scream.ogg"),
+            ("funny", "troll.png"),
+            ("empty", "notes.txt"),
+        ] {
+            std::fs::create_dir_all(dir.join(pack)).unwrap();
+            std::fs::write(dir.join(pack).join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn packs_list_their_own_images_and_audio() {
+        let dir = temp_packs("contents");
+        let pack = choose(&dir, Some("spooky"), &[], &mut StdRng::seed_from_u64(0)).unwrap();
+
+        assert_eq!(pack.name, "spooky");
+        assert_eq!(pack.images, [dir.join("spooky/skull.png")]);
+        assert_eq!(pack.audio, [dir.join("spooky/scream.ogg")]);
+        assert!(choose(&dir, Some("missing"), &[], &mut StdRng::seed_from_u64(0)).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn random_packs_need_images() {
+        let dir = temp_packs("random");
+        let mut rng = StdRng::seed_from_u64(2);
+
+        for _ in 0..20 {
+            let pack = choose(&dir, None, &[], &mut rng).unwrap();
+            assert!(
+                ["spooky", "funny"].contains(&pack.name.as_str()),
+                "{}",
+                pack.name
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
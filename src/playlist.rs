@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use rand::{RngCore, seq::SliceRandom};
+
+/// A shuffled order of every file in a media directory, consumed one pick at a time so each
+/// file comes up once before any of them repeats.
+#[derive(Default)]
+pub struct Playlist {
+    queue: Vec<PathBuf>,
+    next: usize,
+}
+
+impl Playlist {
+    /// Returns the next queued file that's still in `files`, reshuffling `files` once the queue
+    /// runs out. Files in `avoid` are put off for the other queued ones, unless they're all that's
+    /// left.
+    pub fn next(
+        &mut self,
+        files: Vec<PathBuf>,
+        avoid: &[PathBuf],
+        rng: &mut impl RngCore,
+    ) -> Option<PathBuf> {
+        // files deleted since the shuffle are skipped, new ones wait for the next round
+        let mut queue: Vec<PathBuf> = self
+            .queue
+            .drain(self.next..)
+            .filter(|path| files.contains(path))
+            .collect();
+
+        if queue.is_empty() {
+            queue = files;
+            queue.shuffle(rng);
+        }
+
+        if let Some(i) = queue.iter().position(|path| !avoid.contains(path)) {
+            queue.swap(0, i);
+        }
+
+        self.queue = queue;
+        self.next = 0;
+
+        let first = self.queue.first().cloned();
+        if first.is_some() {
+            self.next = 1;
+        }
+
+        first
+    }
+
+    /// Drops the current order, the next pick starts a fresh round.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn plays_every_file_once_per_round() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut playlist = Playlist::default();
+        let pool = files(&["a.ogg", "b.ogg", "c.ogg", "d.ogg"]);
+        let mut last = None;
+
+        for _ in 0..5 {
+            let round: HashSet<PathBuf> = (0..pool.len())
+                .map(|_| {
+                    let next = playlist
+                        .next(pool.clone(), last.as_slice(), &mut rng)
+                        .unwrap();
+                    assert_ne!(Some(&next), last.as_ref());
+                    last = Some(next.clone());
+                    next
+                })
+                .collect();
+
+            assert_eq!(round.len(), pool.len());
+        }
+    }
+
+    #[test]
+    fn skips_files_removed_mid_round() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut playlist = Playlist::default();
+
+        let first = playlist
+            .next(files(&["a.ogg", "b.ogg", "c.ogg"]), &[], &mut rng)
+            .unwrap();
+        let remaining: Vec<PathBuf> = files(&["a.ogg", "b.ogg", "c.ogg"])
+            .into_iter()
+            .filter(|path| *path != first)
+            .collect();
+
+        let next = playlist
+            .next(vec![remaining[0].clone()], &[], &mut rng)
+            .unwrap();
+        assert_eq!(next, remaining[0]);
+    }
+
+    #[test]
+    fn avoided_files_wait_their_turn() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut playlist = Playlist::default();
+        let pool = files(&["a.ogg", "b.ogg", "c.ogg"]);
+        let mut picked = Vec::new();
+
+        for _ in 0..pool.len() {
+            let next = playlist.next(pool.clone(), &picked, &mut rng).unwrap();
+            assert!(!picked.contains(&next));
+            picked.push(next);
+        }
+
+        // a new round starts with whatever isn't avoided
+        let next = playlist.next(pool.clone(), &pool[..2], &mut rng).unwrap();
+        assert_eq!(next, pool[2]);
+    }
+
+    #[test]
+    fn empty_directory_has_nothing_to_play() {
+        let mut playlist = Playlist::default();
+        assert!(
+            playlist
+                .next(Vec::new(), &[], &mut StdRng::seed_from_u64(0))
+                .is_none()
+        );
+    }
+}
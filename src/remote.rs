@@ -0,0 +1,144 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use ureq::Agent;
+
+/// How long a single request may take, downloads included.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The files a `source` offers, as URLs that are either absolute or relative to the manifest.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Manifest {
+    images: Vec<String>,
+    audio: Vec<String>,
+}
+
+/// Downloads whatever `source` lists that isn't in `image_dir` or `music_dir` yet. Offline, or
+/// with a broken manifest, the files cached by earlier runs are used as they are.
+pub fn sync(source: &str, image_dir: &Path, music_dir: &Path) -> Result<()> {
+    for dir in [image_dir, music_dir] {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let manifest = match fetch_manifest(&agent, source) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!("using the cached media, couldn't fetch {source}: {e:#}");
+            return Ok(());
+        }
+    };
+
+    let mut downloaded = 0;
+    for (entries, dir) in [(&manifest.images, image_dir), (&manifest.audio, music_dir)] {
+        for entry in entries {
+            let url = resolve_url(source, entry);
+            let Some(name) = file_name(&url) else {
+                warn!("skipping {url}, it doesn't end in a file name");
+                continue;
+            };
+
+            let path = dir.join(name);
+            if path.exists() {
+                continue;
+            }
+
+            match download(&agent, &url, &path) {
+                Ok(()) => downloaded += 1,
+                Err(e) => warn!("couldn't download {url}: {e:#}"),
+            }
+        }
+    }
+
+    info!("downloaded {downloaded} new files from {source}");
+    Ok(())
+}
+
+fn fetch_manifest(agent: &Agent, source: &str) -> Result<Manifest> {
+    let body = agent.get(source).call()?.body_mut().read_to_string()?;
+    serde_json::from_str(&body).context("parsing the manifest")
+}
+
+/// Saves `url` to `path`, through a `.part` file so an interrupted download isn't mistaken
+/// for a cached one on the next run.
+fn download(agent: &Agent, url: &str, path: &Path) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let response = agent.get(url).call()?;
+    let mut reader = response.into_body().into_reader();
+    let mut file =
+        File::create(&partial).with_context(|| format!("creating {}", path.display()))?;
+
+    if let Err(e) = io::copy(&mut reader, &mut file) {
+        let _ = fs::remove_file(&partial);
+        return Err(e.into());
+    }
+
+    fs::rename(&partial, path).with_context(|| format!("saving {}", path.display()))?;
+    info!("downloaded {}", path.display());
+    Ok(())
+}
+
+/// `entry` as an absolute URL, relative entries sit next to the manifest at `source`.
+fn resolve_url(source: &str, entry: &str) -> String {
+    if entry.starts_with("http://") || entry.starts_with("https://") {
+        return entry.to_string();
+    }
+
+    let base = source.split(['?', '#']).next().unwrap_or(source);
+    let base = base.rsplit_once('/').map_or(base, |(dir, _)| dir);
+    format!("{base}/{}", entry.trim_start_matches('/'))
+}
+
+/// The last path segment of `url`, if it's usable as a file name in the cache.
+fn file_name(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let name = path.rsplit('/').next()?;
+
+    (!name.is_empty() && !name.starts_with('.') && !name.contains('\\')).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_resolve_next_to_the_manifest() {
+        let source = "https://example.com/memes/manifest.json?v=2";
+
+        assert_eq!(
+            resolve_url(source, "skull.png"),
+            "https://example.com/memes/skull.png"
+        );
+        assert_eq!(
+            resolve_url(source, "audio/ef3.ogg"),
+            "https://example.com/memes/audio/ef3.ogg"
+        );
+        assert_eq!(
+            resolve_url(source, "http://cdn.example.com/troll.gif"),
+            "http://cdn.example.com/troll.gif"
+        );
+    }
+
+    #[test]
+    fn cached_files_are_named_after_the_url() {
+        assert_eq!(
+            file_name("https://example.com/a/skull.png?raw=1"),
+            Some("skull.png")
+        );
+        assert_eq!(file_name("https://example.com/a/"), None);
+        assert_eq!(file_name("https://example.com/a/.."), None);
+        assert_eq!(file_name("https://example.com/a/..\\evil.png"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use image::RgbaImage;
+
+use crate::{app::render_still, config::Config};
+
+/// Draws `image` onto a `size` canvas like a show would and saves it to `out`, skipping Wayland
+/// entirely.
+pub fn run(config: &Config, image: &Path, out: &Path, size: (u32, u32)) -> Result<()> {
+    let canvas = render_still(config, image, size.0, size.1)?;
+
+    RgbaImage::from_raw(size.0, size.1, canvas)
+        .ok_or_else(|| anyhow!("canvas doesn't match {}x{}", size.0, size.1))?
+        .save(out)
+        .with_context(|| format!("saving {}", out.display()))?;
+
+    println!("rendered {} to {}", image.display(), out.display());
+    Ok(())
+}
+
+/// Parses a `WIDTHxHEIGHT` size like `1920x1080`.
+pub fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow!("size {s:?} must look like 1920x1080");
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    let size = (
+        width.trim().parse().map_err(|_| invalid())?,
+        height.trim().parse().map_err(|_| invalid())?,
+    );
+
+    if size.0 == 0 || size.1 == 0 {
+        return Err(invalid());
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::cache::Image;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1920x1080").unwrap(), (1920, 1080));
+
+        for value in ["", "1920", "0x1080", "1920x", "axb", "1920*1080"] {
+            assert!(parse_size(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn renders_to_png() {
+        let dir = std::env::temp_dir().join(format!("phonk-wl-render-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (image, out) = (dir.join("red.png"), dir.join("frame.png"));
+        Image::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+            .save(&image)
+            .unwrap();
+        let config = Config {
+            background: Rgba([0, 0, 255, 255]),
+            ..Config::default()
+        };
+
+        run(&config, &image, &out, (4, 2)).unwrap();
+
+        let frame = image::open(&out).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (4, 2));
+        assert_eq!(frame.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(frame.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
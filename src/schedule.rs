@@ -0,0 +1,201 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{Datelike, NaiveDate, NaiveTime};
+use serde::Deserialize;
+
+/// A daily range of local time like `09:00-17:00`. Ranges ending before they start wrap past
+/// midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ActiveWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveWindow {
+    /// Whether `time` falls inside the window, the start is inclusive and the end exclusive.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for ActiveWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("active hours {s:?} must look like HH:MM-HH:MM"))?;
+
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .with_context(|| format!("invalid time {time:?} in active hours {s:?}"))
+        };
+
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for ActiveWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Whether shows are allowed at `time`, no windows at all means always.
+pub fn is_active(windows: &[ActiveWindow], time: NaiveTime) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(time))
+}
+
+/// Whether audio is silenced at `time`, no windows at all means never.
+pub fn is_quiet(windows: &[ActiveWindow], time: NaiveTime) -> bool {
+    windows.iter().any(|window| window.contains(time))
+}
+
+/// An image, and optionally a clip, that every show on `date` uses instead of a random pick.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DatedShow {
+    pub date: ShowDate,
+    pub image: PathBuf,
+    pub audio: Option<PathBuf>,
+}
+
+/// A `YYYY-MM-DD` date, or `MM-DD` for every year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ShowDate {
+    On(NaiveDate),
+    Yearly { month: u32, day: u32 },
+}
+
+impl ShowDate {
+    fn matches(&self, date: NaiveDate) -> bool {
+        match *self {
+            Self::On(on) => on == date,
+            Self::Yearly { month, day } => date.month() == month && date.day() == day,
+        }
+    }
+}
+
+impl FromStr for ShowDate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(date) = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d") {
+            return Ok(Self::On(date));
+        }
+
+        let invalid = || anyhow!("date {s:?} must look like YYYY-MM-DD or MM-DD");
+        let (month, day) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let (month, day) = (
+            month.parse().map_err(|_| invalid())?,
+            day.parse().map_err(|_| invalid())?,
+        );
+
+        // a leap year, so Feb 29 is allowed
+        if NaiveDate::from_ymd_opt(2024, month, day).is_none() {
+            bail!("{s:?} is not a day of the year");
+        }
+
+        Ok(Self::Yearly { month, day })
+    }
+}
+
+impl TryFrom<String> for ShowDate {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// The show planned for `date`, those for that exact date before yearly ones.
+pub fn dated_show(shows: &[DatedShow], date: NaiveDate) -> Option<&DatedShow> {
+    shows
+        .iter()
+        .find(|show| matches!(show.date, ShowDate::On(_)) && show.date.matches(date))
+        .or_else(|| shows.iter().find(|show| show.date.matches(date)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window: ActiveWindow = "09:00-17:30".parse().unwrap();
+        assert_eq!(window.start, at(9, 0));
+        assert_eq!(window.end, at(17, 30));
+
+        for value in ["", "09:00", "9-17", "09:00-25:00", "09:00_17:00"] {
+            assert!(value.parse::<ActiveWindow>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn overnight_windows_wrap_past_midnight() {
+        let window: ActiveWindow = "22:00-06:00".parse().unwrap();
+
+        assert!(window.contains(at(23, 0)));
+        assert!(window.contains(at(0, 0)));
+        assert!(window.contains(at(5, 59)));
+        assert!(!window.contains(at(6, 0)));
+        assert!(!window.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn exact_dates_win_over_yearly_ones() {
+        let show = |date: &str, image: &str| DatedShow {
+            date: date.parse().unwrap(),
+            image: PathBuf::from(image),
+            audio: None,
+        };
+        let shows = [
+            show("10-31", "pumpkin.png"),
+            show("2026-10-31", "ghost.png"),
+        ];
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+
+        let image = |date| dated_show(&shows, date).map(|show| show.image.to_str().unwrap());
+        assert_eq!(image(day(2026, 10, 31)), Some("ghost.png"));
+        assert_eq!(image(day(2027, 10, 31)), Some("pumpkin.png"));
+        assert_eq!(image(day(2026, 11, 1)), None);
+
+        for value in ["", "13-01", "02-30", "10/31", "2026-02-29"] {
+            assert!(value.parse::<ShowDate>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn any_window_allows_a_show() {
+        let windows = ["09:00-12:00", "13:00-17:00"].map(|w| w.parse().unwrap());
+
+        assert!(is_active(&windows, at(10, 0)));
+        assert!(is_active(&windows, at(16, 59)));
+        assert!(!is_active(&windows, at(12, 30)));
+        assert!(is_active(&[], at(3, 0)));
+    }
+
+    #[test]
+    fn mute_hours_silence_overnight() {
+        let windows = ["23:00-07:00".parse().unwrap()];
+
+        assert!(is_quiet(&windows, at(2, 0)));
+        assert!(!is_quiet(&windows, at(7, 0)));
+        assert!(!is_quiet(&[], at(2, 0)));
+    }
+}
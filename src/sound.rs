@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use anyhow::{Context, Result};
+use rodio::{OutputStream, OutputStreamBuilder, Sink};
+
+/// The audio device and a sink per clip on its mixer, so clips can overlap up to a cap.
+pub struct SoundPool {
+    stream: OutputStream,
+    /// Oldest first, the last one holds the current clip. Never empty.
+    sinks: VecDeque<Sink>,
+    max_sounds: usize,
+}
+
+impl SoundPool {
+    /// Opens the default audio device.
+    pub fn open(max_sounds: usize) -> Result<Self> {
+        let stream =
+            OutputStreamBuilder::open_default_stream().context("opening default audio device")?;
+        let sink = Sink::connect_new(stream.mixer());
+
+        Ok(Self {
+            stream,
+            sinks: VecDeque::from([sink]),
+            max_sounds,
+        })
+    }
+
+    /// The sink of the newest clip.
+    pub fn current(&self) -> &Sink {
+        self.sinks.back().expect("the pool always has a sink")
+    }
+
+    /// Starts a new sink for a clip to play next to the others, stopping the oldest ones past
+    /// the cap.
+    pub fn add(&mut self) -> &Sink {
+        let sink = Sink::connect_new(self.stream.mixer());
+        push_capped(&mut self.sinks, sink, self.max_sounds);
+        self.current()
+    }
+
+    pub fn stop_all(&self) {
+        for sink in &self.sinks {
+            sink.stop();
+        }
+    }
+
+    /// Sets the volume of every clip, the ones mixed in under the current one too.
+    pub fn set_volume_all(&self, volume: f32) {
+        for sink in &self.sinks {
+            sink.set_volume(volume);
+        }
+    }
+
+    pub fn pause_all(&self) {
+        for sink in &self.sinks {
+            sink.pause();
+        }
+    }
+
+    /// Stops every clip but the current one.
+    pub fn stop_older(&mut self) {
+        while self.sinks.len() > 1 {
+            if let Some(oldest) = self.sinks.pop_front() {
+                oldest.stop();
+            }
+        }
+    }
+}
+
+/// Left and right volumes that pan a sound by `pan`, from -1.0 (left) to 1.0 (right). The side
+/// it moves towards stays at full volume.
+pub fn pan_volumes(pan: f32) -> Vec<f32> {
+    let pan = pan.clamp(-1.0, 1.0);
+    vec![(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)]
+}
+
+/// Appends `sink`, first dropping finished ones and then the oldest until at most `cap` are left.
+fn push_capped(sinks: &mut VecDeque<Sink>, sink: Sink, cap: usize) {
+    sinks.retain(|sink| !sink.empty());
+    sinks.push_back(sink);
+
+    while sinks.len() > cap.max(1) {
+        if let Some(oldest) = sinks.pop_front() {
+            oldest.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rodio::source::{SineWave, Source};
+
+    use super::*;
+
+    fn playing_sink() -> Sink {
+        let (sink, _output) = Sink::new();
+        sink.append(SineWave::new(440.0).take_duration(Duration::from_secs(60)));
+        sink
+    }
+
+    #[test]
+    fn panning_fades_the_far_side() {
+        assert_eq!(pan_volumes(0.0), [1.0, 1.0]);
+        assert_eq!(pan_volumes(-1.0), [1.0, 0.0]);
+        assert_eq!(pan_volumes(0.5), [0.5, 1.0]);
+        assert_eq!(pan_volumes(3.0), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn the_oldest_sounds_make_way() {
+        let mut sinks = VecDeque::new();
+        for _ in 0..3 {
+            push_capped(&mut sinks, playing_sink(), 2);
+        }
+        assert_eq!(sinks.len(), 2);
+
+        // finished sinks go first
+        sinks.push_front(Sink::new().0);
+        push_capped(&mut sinks, playing_sink(), 3);
+        assert_eq!(sinks.len(), 3);
+        assert!(sinks.iter().all(|sink| !sink.empty()));
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// A state change printed to stdout as a JSON line with `--status-json`, for status bars.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Status {
+    Show {
+        image: Option<PathBuf>,
+        audio: Option<PathBuf>,
+    },
+    Hide,
+}
+
+/// Prints `status` to stdout. Fails once nothing reads it anymore, like when the status bar
+/// exited.
+pub fn print(status: &Status) -> Result<()> {
+    write(&mut io::stdout().lock(), status)
+}
+
+fn write(out: &mut impl Write, status: &Status) -> Result<()> {
+    writeln!(out, "{}", serde_json::to_string(status)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_are_tagged_json() {
+        let show = Status::Show {
+            image: Some(PathBuf::from("/images/skull.png")),
+            audio: None,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&show).unwrap(),
+            r#"{"event":"show","image":"/images/skull.png","audio":null}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Status::Hide).unwrap(),
+            r#"{"event":"hide"}"#
+        );
+    }
+
+    #[test]
+    fn a_closed_reader_is_an_error() {
+        struct Closed;
+
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut line = Vec::new();
+        write(&mut line, &Status::Hide).unwrap();
+        assert_eq!(line, b"{\"event\":\"hide\"}\n");
+        assert!(write(&mut Closed, &Status::Hide).is_err());
+    }
+}
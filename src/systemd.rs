@@ -0,0 +1,59 @@
+use std::{
+    env,
+    ffi::OsStr,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+};
+
+use anyhow::Result;
+use log::debug;
+
+/// Tells systemd about a state change like `READY=1`, when running as a `Type=notify` unit.
+/// Does nothing otherwise.
+pub fn notify(state: &str) {
+    let Some(socket) = env::var_os("NOTIFY_SOCKET").filter(|socket| !socket.is_empty()) else {
+        return;
+    };
+
+    if let Err(e) = notify_to(&socket, state) {
+        debug!("couldn't notify systemd of {state}: {e:#}");
+    }
+}
+
+/// Sends `state` to the notification socket at `socket`, a path or an abstract name starting
+/// with `@`.
+fn notify_to(socket: &OsStr, state: &str) -> Result<()> {
+    let address = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_the_state_to_the_socket() {
+        let path = env::temp_dir().join(format!("phonk-wl-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+
+        let mut buf = [0; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
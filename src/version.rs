@@ -0,0 +1,10 @@
+/// What `--version` prints after the program name: the crate version, the commit it was built
+/// from and when.
+pub const LONG: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("PHONK_WL_COMMIT"),
+    ", built ",
+    env!("PHONK_WL_BUILD_DATE"),
+    ")"
+);
@@ -0,0 +1,74 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+use log::{debug, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use smithay_client_toolkit::reexports::calloop::{
+    LoopHandle, RegistrationToken,
+    channel::{Event, channel},
+    timer::{TimeoutAction, Timer},
+};
+
+use crate::app::App;
+
+/// How long the media directories have to stay quiet before the listings are refreshed, so
+/// copying a batch of files refreshes them once.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches `dirs` and tells the app whenever files appear in or disappear from them. Dropping
+/// the returned watcher stops watching.
+pub fn watch(
+    loop_handle: &LoopHandle<'static, App>,
+    dirs: &[PathBuf],
+) -> Result<RecommendedWatcher> {
+    let (sender, events) = channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = sender.send(event);
+    })?;
+
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("watching {}", dir.display()))?;
+    }
+
+    let timers = loop_handle.clone();
+    let mut pending: Option<RegistrationToken> = None;
+    loop_handle
+        .insert_source(events, move |event, _, app| {
+            let event = match event {
+                Event::Msg(Ok(event)) => event,
+                Event::Msg(Err(e)) => {
+                    warn!("media directory watcher: {e}");
+                    return;
+                }
+                Event::Closed => return,
+            };
+
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+
+            // every event pushes the refresh back, it runs once things settle down
+            if let Some(token) = pending.take() {
+                timers.remove(token);
+            }
+
+            let refresh = timers.insert_source(Timer::from_duration(DEBOUNCE), |_, _, app| {
+                debug!("media directories changed");
+                app.media_changed();
+                TimeoutAction::Drop
+            });
+
+            match refresh {
+                Ok(token) => pending = Some(token),
+                Err(e) => {
+                    warn!("scheduling media refresh: {e}");
+                    app.media_changed();
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting media watcher: {e}"))?;
+
+    Ok(watcher)
+}